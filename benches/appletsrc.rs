@@ -0,0 +1,106 @@
+//! Scan time for synthetic appletsrc files of growing size.
+//!
+//! Run with `cargo bench`. Each size is loaded a few times with
+//! [`parser::load_appletsrc`], without the XDG cascade, and the best run is
+//! kept; the bench fails if doubling the number of applets more than
+//! triples the load time.
+
+use std::fmt::Write as _;
+use std::hint::black_box;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use kdesktop_copycat::parser;
+
+const SIZES: [usize; 4] = [1_250, 2_500, 5_000, 10_000];
+const RUNS: usize = 3;
+const APPLETS_PER_CONTAINMENT: usize = 50;
+
+/// Panels and desktops with `applets` widgets in total, each with a
+/// `[Configuration]` group, a `[Configuration][General]` group and an
+/// `AppletOrder` entry, like a large multi-monitor setup.
+fn synthetic_appletsrc(applets: usize) -> String {
+    let mut out = String::new();
+    let containments = applets.div_ceil(APPLETS_PER_CONTAINMENT);
+    let mut aid = containments + 1;
+    for cid in 1..=containments {
+        let first = aid;
+        let count = APPLETS_PER_CONTAINMENT.min(applets - (cid - 1) * APPLETS_PER_CONTAINMENT);
+        aid += count;
+        let order: Vec<String> = (first..aid).map(|a| a.to_string()).collect();
+        let plugin = if cid % 2 == 0 {
+            "org.kde.plasma.folder"
+        } else {
+            "org.kde.panel"
+        };
+        writeln!(out, "[Containments][{cid}]").unwrap();
+        writeln!(out, "activityId=").unwrap();
+        writeln!(out, "formfactor=2").unwrap();
+        writeln!(out, "immutability=1").unwrap();
+        writeln!(out, "lastScreen={}", cid % 4).unwrap();
+        writeln!(out, "location=4").unwrap();
+        writeln!(out, "plugin={plugin}").unwrap();
+        writeln!(out, "wallpaperplugin=org.kde.image").unwrap();
+        writeln!(out).unwrap();
+        writeln!(out, "[Containments][{cid}][General]").unwrap();
+        writeln!(out, "AppletOrder={}", order.join(";")).unwrap();
+        writeln!(out).unwrap();
+        for a in first..aid {
+            writeln!(out, "[Containments][{cid}][Applets][{a}]").unwrap();
+            writeln!(out, "immutability=1").unwrap();
+            writeln!(out, "plugin=org.kde.plasma.synthetic{}", a % 17).unwrap();
+            writeln!(out).unwrap();
+            writeln!(out, "[Containments][{cid}][Applets][{a}][Configuration]").unwrap();
+            writeln!(out, "PreloadWeight=42").unwrap();
+            writeln!(out, "popupHeight=400").unwrap();
+            writeln!(out).unwrap();
+            writeln!(
+                out,
+                "[Containments][{cid}][Applets][{a}][Configuration][General]"
+            )
+            .unwrap();
+            writeln!(out, "favorites=preferred://browser,org.kde.dolphin.desktop").unwrap();
+            writeln!(out, "showText=true").unwrap();
+            writeln!(out).unwrap();
+        }
+    }
+    out
+}
+
+fn scan(file: &Path) -> Duration {
+    let path = file.display().to_string();
+    let start = Instant::now();
+    let layout = parser::load_appletsrc(&path, &[]).expect("loading appletsrc");
+    let elapsed = start.elapsed();
+    black_box(layout);
+    elapsed
+}
+
+fn main() {
+    let dir = std::env::temp_dir().join(format!("kdesktop-copycat-bench-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).expect("creating bench dir");
+
+    let mut results = Vec::new();
+    for applets in SIZES {
+        let file = dir.join(format!("appletsrc-{applets}"));
+        std::fs::write(&file, synthetic_appletsrc(applets)).expect("writing appletsrc");
+        let best = (0..RUNS).map(|_| scan(&file)).min().unwrap();
+        println!(
+            "{applets:>6} applets: {:>8.1} ms ({:.2} µs/applet)",
+            best.as_secs_f64() * 1e3,
+            best.as_secs_f64() * 1e6 / applets as f64
+        );
+        results.push(best);
+    }
+    let _ = std::fs::remove_dir_all(&dir);
+
+    for (w, pair) in results.windows(2).enumerate() {
+        let ratio = pair[1].as_secs_f64() / pair[0].as_secs_f64();
+        assert!(
+            ratio < 3.0,
+            "scan time grew {ratio:.1}x from {} to {} applets; expected ~2x",
+            SIZES[w],
+            SIZES[w + 1]
+        );
+    }
+}
@@ -0,0 +1,72 @@
+use anyhow::Result;
+use std::path::PathBuf;
+
+use crate::kconfig;
+use crate::model::{Activity, Layout};
+
+/// Reads the activities from kactivitymanagerdrc (through the XDG cascade
+/// over `config_dirs`) into `layout.activities`, each with the desktops whose
+/// `activityId` points at it.
+///
+/// kactivitymanagerd lists activities as `[activities] <id>=<name>`, with
+/// icons and descriptions in `[activities-icons]` and
+/// `[activities-descriptions]`. A missing file leaves `activities` empty.
+pub fn attach_activities(
+    layout: &mut Layout,
+    rc_path: &str,
+    config_dirs: &[PathBuf],
+) -> Result<()> {
+    let rc = kconfig::load_cascade(
+        "kactivitymanagerdrc",
+        rc_path,
+        config_dirs,
+        &mut layout.diagnostics,
+    )?;
+    let ini = &rc.effective;
+    let Some(names) = ini.group(&["activities"]) else {
+        return Ok(());
+    };
+    let icons = ini.group(&["activities-icons"]);
+    let descriptions = ini.group(&["activities-descriptions"]);
+    let current = ini
+        .group(&["main"])
+        .and_then(|g| g.get("currentActivity"))
+        .map(|s| s.trim());
+
+    // In the order of the user's file; activities only a system file lists
+    // come last.
+    let mut ids = rc
+        .user
+        .as_ref()
+        .map(|t| t.key_order(&["activities"]))
+        .unwrap_or_default();
+    ids.retain(|id| names.contains_key(id));
+    ids.extend(
+        names
+            .keys()
+            .filter(|id| !ids.contains(id))
+            .cloned()
+            .collect::<Vec<_>>(),
+    );
+
+    for id in &ids {
+        let name = &names[id];
+        let lookup = |g: Option<&std::collections::BTreeMap<String, String>>| {
+            g.and_then(|g| g.get(id)).filter(|v| !v.is_empty()).cloned()
+        };
+        layout.activities.push(Activity {
+            id: id.clone(),
+            name: name.clone(),
+            icon: lookup(icons),
+            description: lookup(descriptions),
+            current: current == Some(id.as_str()),
+            containments: layout
+                .containments
+                .iter()
+                .filter(|c| c.activity() == Some(id.as_str()))
+                .map(|c| c.id)
+                .collect(),
+        });
+    }
+    Ok(())
+}
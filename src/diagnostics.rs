@@ -0,0 +1,162 @@
+use anyhow::{bail, Result};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+/// What kind of anomaly the parser recovered from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum DiagnosticKind {
+    /// `[...` or `[]`: the group and its keys are ignored
+    MalformedHeader,
+    /// A line that is neither a header, a comment nor `key=value`
+    MissingEquals,
+    /// `key=value` before the first group
+    OrphanKey,
+    /// Same key twice in one group; the last value wins
+    DuplicateKey,
+    /// An `AppletOrder` entry that is not an applet id
+    BadAppletOrder,
+    /// `[Containments][x]` or `[Containments][N][Applets][x]` with a non-numeric id
+    BadId,
+}
+
+impl DiagnosticKind {
+    pub fn code(self) -> &'static str {
+        match self {
+            DiagnosticKind::MalformedHeader => "malformed-header",
+            DiagnosticKind::MissingEquals => "missing-equals",
+            DiagnosticKind::OrphanKey => "orphan-key",
+            DiagnosticKind::DuplicateKey => "duplicate-key",
+            DiagnosticKind::BadAppletOrder => "bad-applet-order",
+            DiagnosticKind::BadId => "bad-id",
+        }
+    }
+
+    pub fn severity(self) -> Severity {
+        match self {
+            DiagnosticKind::MalformedHeader => Severity::Error,
+            _ => Severity::Warning,
+        }
+    }
+}
+
+/// One parse anomaly, located in the source file.
+///
+/// `line` and `column` are 1-based; `len` is the width of the highlighted
+/// span in characters.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub kind: DiagnosticKind,
+    pub message: String,
+    pub file: String,
+    pub line: usize,
+    pub column: usize,
+    pub len: usize,
+    /// The offending source line, for rendering
+    pub snippet: String,
+}
+
+impl Diagnostic {
+    pub fn new(
+        kind: DiagnosticKind,
+        message: impl Into<String>,
+        file: &str,
+        line: usize,
+        snippet: &str,
+        column: usize,
+        len: usize,
+    ) -> Self {
+        Diagnostic {
+            severity: kind.severity(),
+            kind,
+            message: message.into(),
+            file: file.to_string(),
+            line,
+            column,
+            len: len.max(1),
+            snippet: snippet.trim_end_matches(['\r', '\n']).to_string(),
+        }
+    }
+}
+
+/// Renders like rustc:
+///
+/// ```text
+/// warning[duplicate-key]: duplicate key `plugin` in [Containments][1]
+///  --> appletsrc:12:1
+///    |
+/// 12 | plugin=org.kde.panel
+///    | ^^^^^^
+/// ```
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sev = match self.severity {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        };
+        let gutter = self.line.to_string().len();
+        let pad = " ".repeat(gutter);
+        writeln!(f, "{sev}[{}]: {}", self.kind.code(), self.message)?;
+        writeln!(f, "{pad}--> {}:{}:{}", self.file, self.line, self.column)?;
+        writeln!(f, "{pad} |")?;
+        writeln!(f, "{} | {}", self.line, self.snippet)?;
+        write!(
+            f,
+            "{pad} | {}{}",
+            " ".repeat(self.column.saturating_sub(1)),
+            "^".repeat(self.len)
+        )
+    }
+}
+
+/// Puts diagnostics in file, line and column order.
+pub(crate) fn sort(diags: &mut [Diagnostic]) {
+    diags.sort_by(|a, b| (&a.file, a.line, a.column).cmp(&(&b.file, b.line, b.column)));
+}
+
+/// Prints diagnostics to stderr; with `strict`, any diagnostic is an error.
+pub fn report(diags: &[Diagnostic], strict: bool) -> Result<()> {
+    for d in diags {
+        eprintln!("{d}\n");
+    }
+    if strict && !diags.is_empty() {
+        bail!("{} parse diagnostic(s) and --strict is set", diags.len());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sorts_by_file_line_and_column() {
+        let d = |file: &str, line, column| {
+            Diagnostic::new(
+                DiagnosticKind::MissingEquals,
+                "",
+                file,
+                line,
+                "x",
+                column,
+                1,
+            )
+        };
+        let mut diags = vec![d("b", 1, 1), d("a", 2, 1), d("a", 1, 5), d("a", 1, 2)];
+        sort(&mut diags);
+        let order: Vec<_> = diags
+            .iter()
+            .map(|d| (&d.file[..], d.line, d.column))
+            .collect();
+        assert_eq!(order, [("a", 1, 2), ("a", 1, 5), ("a", 2, 1), ("b", 1, 1)]);
+    }
+}
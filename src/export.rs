@@ -1,19 +1,114 @@
 use anyhow::{Context, Result};
-use std::collections::BTreeSet;
+use std::collections::{BTreeMap, BTreeSet};
 use std::fs;
 use std::path::{Path, PathBuf};
 use std::time::{SystemTime, UNIX_EPOCH};
 
 use walkdir::WalkDir;
 
-use crate::model::Layout;
-use crate::scripts;
+use crate::kwin::{self, AURORAE, AURORAE_SVG_PREFIX};
+use crate::model::{KWinPluginKind, KWinScan, Layout};
+use crate::parser;
+use crate::paths::Paths;
+use crate::scripts::{self, KWinPackages};
 
+/// Writes bundles: layout.json, restore scripts, and optionally a snapshot
+/// of the appletsrc and the user-installed plasmoids and KWin packages the
+/// layout uses.
+///
+/// ```no_run
+/// # fn main() -> anyhow::Result<()> {
+/// use kdesktop_copycat::{export::Exporter, parser};
+///
+/// let layout = parser::parse_appletsrc("plasma-org.kde.plasma.desktop-appletsrc")?;
+/// let bundle = Exporter::new("/tmp/bundles")
+///     .bundle_plasmoids(false)
+///     .map_screen("DP-2", "HDMI-A-1")
+///     .export(&layout)?;
+/// println!("{}", bundle.dir.display());
+/// # Ok(())
+/// # }
+/// ```
 #[derive(Debug, Clone)]
-pub struct ExportOpts {
-    pub out: PathBuf,
-    pub snapshot: bool,
-    pub bundle_plasmoids: bool,
+pub struct Exporter {
+    out: PathBuf,
+    paths: Paths,
+    snapshot: bool,
+    bundle_plasmoids: bool,
+    bundle_kwin_packages: bool,
+    screen_map: BTreeMap<String, String>,
+    source_bundle: Option<PathBuf>,
+}
+
+/// What [`Exporter::export`] wrote.
+#[derive(Debug, Clone)]
+pub struct Bundle {
+    /// The new `plasma-layout-bundle-<timestamp>` directory
+    pub dir: PathBuf,
+    /// One line per file or folder written, relative to `dir`
+    pub contents: Vec<String>,
+    /// Options that had no effect on this layout
+    pub warnings: Vec<String>,
+}
+
+impl Exporter {
+    /// Bundles go into a new directory below `out`. Snapshot, plasmoids and
+    /// KWin packages are on by default; files are looked up through
+    /// [`Paths::from_env`].
+    pub fn new(out: impl Into<PathBuf>) -> Self {
+        Exporter {
+            out: out.into(),
+            paths: Paths::from_env(),
+            snapshot: true,
+            bundle_plasmoids: true,
+            bundle_kwin_packages: true,
+            screen_map: BTreeMap::new(),
+            source_bundle: None,
+        }
+    }
+
+    /// Where user plasmoids and system data (to tell apart bundled wallpapers) live.
+    pub fn paths(mut self, paths: Paths) -> Self {
+        self.paths = paths;
+        self
+    }
+
+    /// Include `snapshot/` with the appletsrc written from the layout.
+    pub fn snapshot(mut self, on: bool) -> Self {
+        self.snapshot = on;
+        self
+    }
+
+    /// Include `plasmoids/` with the user-installed plasmoids the layout uses.
+    pub fn bundle_plasmoids(mut self, on: bool) -> Self {
+        self.bundle_plasmoids = on;
+        self
+    }
+
+    /// Include the user-installed KWin scripts, effects and window decoration
+    /// that kwinrc switches on, for restore-kwin.sh to install.
+    pub fn bundle_kwin_packages(mut self, on: bool) -> Self {
+        self.bundle_kwin_packages = on;
+        self
+    }
+
+    /// Restore what was on output `from` onto output `to` (`--map-screen`).
+    pub fn map_screen(mut self, from: impl Into<String>, to: impl Into<String>) -> Self {
+        self.screen_map.insert(from.into(), to.into());
+        self
+    }
+
+    /// Bundle the layout was read from; its wallpapers and packages stand
+    /// in for files that are not on this machine.
+    pub fn source_bundle(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.source_bundle = Some(dir.into());
+        self
+    }
+
+    /// Writes a bundle for `layout`.
+    pub fn export(&self, layout: &Layout) -> Result<Bundle> {
+        export_bundle(layout, self)
+    }
 }
 
 fn mkdirp(p: &Path) -> Result<()> {
@@ -37,14 +132,6 @@ fn write_file(p: &Path, content: &str, mode_executable: bool) -> Result<()> {
     Ok(())
 }
 
-fn copy_file(src: &Path, dst: &Path) -> Result<()> {
-    if let Some(parent) = dst.parent() {
-        mkdirp(parent)?;
-    }
-    fs::copy(src, dst).with_context(|| format!("copy {} -> {}", src.display(), dst.display()))?;
-    Ok(())
-}
-
 fn copy_dir_recursive(src: &Path, dst: &Path) -> Result<()> {
     for entry in WalkDir::new(src) {
         let entry = entry?;
@@ -64,25 +151,162 @@ fn copy_dir_recursive(src: &Path, dst: &Path) -> Result<()> {
     Ok(())
 }
 
-fn home_dir() -> Option<PathBuf> {
-    dirs::home_dir()
+fn user_plasmoid_dir(paths: &Paths, plugin_id: &str) -> PathBuf {
+    paths.user_data(&format!("plasma/plasmoids/{plugin_id}"))
+}
+
+fn system_plasmoid_dir(paths: &Paths, plugin_id: &str) -> Option<PathBuf> {
+    paths
+        .system_data(&format!("plasma/plasmoids/{plugin_id}"))
+        .into_iter()
+        .find(|p| p.is_dir())
 }
 
-fn user_plasmoid_dir(plugin_id: &str) -> Option<PathBuf> {
-    let home = home_dir()?;
-    Some(home.join(".local/share/plasma/plasmoids").join(plugin_id))
+/// Copies the user-installed KWin scripts and effects that kwinrc switches
+/// on, and the Aurorae theme or QML decoration in use, into `dir` (the
+/// bundle's `kwin/`). Packages under the system data dirs ship with KWin or
+/// the distribution and are left out; C++ decorations cannot be bundled.
+///
+/// When re-exporting a bundle, packages missing here are taken from the
+/// source bundle's `kwin/`.
+fn bundle_kwin_packages(
+    kwin: &KWinScan,
+    paths: &Paths,
+    source_bundle: Option<&Path>,
+    dir: &Path,
+) -> Result<KWinPackages> {
+    let bundled = |rel: &str| {
+        source_bundle
+            .map(|b| b.join("kwin").join(rel))
+            .filter(|p| p.is_dir())
+    };
+    let mut packages = KWinPackages::default();
+
+    for p in kwin.plugins.iter().filter(|p| p.enabled) {
+        let (sub, ids) = match p.kind {
+            Some(KWinPluginKind::Script) => ("scripts", &mut packages.scripts),
+            Some(KWinPluginKind::Effect) => ("effects", &mut packages.effects),
+            None => continue,
+        };
+        let rel = format!("{sub}/{}", p.id);
+        let installed = p
+            .metadata
+            .as_deref()
+            .and_then(|m| Path::new(m).parent())
+            .filter(|d| d.starts_with(&paths.data_home) && d.is_dir())
+            .map(Path::to_path_buf);
+        let Some(src) = installed.or_else(|| bundled(&rel)) else {
+            continue;
+        };
+        copy_dir_recursive(&src, &dir.join(&rel))?;
+        ids.push(p.id.clone());
+    }
+
+    let deco = &kwin.summary.decoration;
+    if let (Some(AURORAE), Some(theme)) =
+        (deco.get("library").map(String::as_str), deco.get("theme"))
+    {
+        let (name, sub, user_rel, slot) = match theme.strip_prefix(AURORAE_SVG_PREFIX) {
+            Some(name) => (name, "aurorae", "aurorae/themes", &mut packages.aurorae),
+            None => (
+                theme.as_str(),
+                "decorations",
+                "kwin/decorations",
+                &mut packages.decoration,
+            ),
+        };
+        let rel = format!("{sub}/{name}");
+        let user = Some(paths.user_data(&format!("{user_rel}/{name}"))).filter(|p| p.is_dir());
+        if let Some(src) = user.or_else(|| bundled(&rel)) {
+            copy_dir_recursive(&src, &dir.join(&rel))?;
+            *slot = Some(name.to_string());
+        }
+    }
+    Ok(packages)
 }
 
-fn system_plasmoid_dir(plugin_id: &str) -> Option<PathBuf> {
-    let p1 = PathBuf::from("/usr/share/plasma/plasmoids").join(plugin_id);
-    if p1.is_dir() {
-        return Some(p1);
+/// Copies the local images and slideshow folders desktop wallpapers point at
+/// into `dir`, as `<containment id>-<file name>`. Files under the system data
+/// dirs ship with Plasma or the distribution and are left out, as are paths
+/// that no longer exist.
+///
+/// When re-exporting a bundle, files missing here are taken from the
+/// source bundle's `wallpapers/`, under the name its layout.json recorded
+/// in `Wallpaper::bundled`.
+///
+/// Returns path as written in the config → name under `dir`.
+fn bundle_wallpapers(
+    layout: &Layout,
+    paths: &Paths,
+    source_bundle: Option<&Path>,
+    dir: &Path,
+) -> Result<BTreeMap<String, String>> {
+    let mut out = BTreeMap::new();
+    let mut taken = BTreeSet::new();
+    for c in &layout.containments {
+        let Some(wp) = &c.wallpaper else {
+            continue;
+        };
+        for file in &wp.files {
+            if out.contains_key(file) {
+                continue;
+            }
+            let decoded = PathBuf::from(percent_decode(file));
+            if paths.data_dirs.iter().any(|d| decoded.starts_with(d)) {
+                continue;
+            }
+            let base = decoded
+                .file_name()
+                .map(|n| n.to_string_lossy().into_owned())
+                .unwrap_or_else(|| "wallpaper".to_string());
+            let bundled = source_bundle
+                .zip(wp.bundled.get(file))
+                .map(|(b, name)| b.join("wallpapers").join(name));
+            let src = [Some(PathBuf::from(file)), Some(decoded), bundled]
+                .into_iter()
+                .flatten()
+                .find(|p| p.exists());
+            let Some(src) = src else {
+                continue;
+            };
+            let mut name = format!("{}-{base}", c.id);
+            let mut n = 1;
+            while !taken.insert(name.clone()) {
+                name = format!("{}-{n}-{base}", c.id);
+                n += 1;
+            }
+            mkdirp(dir)?;
+            if src.is_dir() {
+                copy_dir_recursive(&src, &dir.join(&name))?;
+            } else {
+                fs::copy(&src, dir.join(&name))
+                    .with_context(|| format!("copy {} -> {}", src.display(), name))?;
+            }
+            out.insert(file.clone(), name);
+        }
     }
-    let p2 = PathBuf::from("/usr/local/share/plasma/plasmoids").join(plugin_id);
-    if p2.is_dir() {
-        return Some(p2);
+    Ok(out)
+}
+
+/// Decodes `%XX` escapes of `file://` URLs; invalid escapes are kept.
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|h| std::str::from_utf8(h).ok())
+            .and_then(|h| u8::from_str_radix(h, 16).ok());
+        if let (b'%', Some(b)) = (bytes[i], hex) {
+            out.push(b);
+            i += 3;
+            continue;
+        }
+        out.push(bytes[i]);
+        i += 1;
     }
-    None
+    String::from_utf8_lossy(&out).into_owned()
 }
 
 fn collect_plasmoid_ids(layout: &Layout) -> BTreeSet<String> {
@@ -98,7 +322,7 @@ fn collect_plasmoid_ids(layout: &Layout) -> BTreeSet<String> {
     out
 }
 
-pub fn export_bundle(layout: &Layout, opts: ExportOpts) -> Result<()> {
+fn export_bundle(layout: &Layout, opts: &Exporter) -> Result<Bundle> {
     let ts = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
     let bundle_dir = opts.out.join(format!("plasma-layout-bundle-{}", ts));
     let scripts_dir = bundle_dir.join("scripts");
@@ -108,14 +332,56 @@ pub fn export_bundle(layout: &Layout, opts: ExportOpts) -> Result<()> {
     mkdirp(&bundle_dir)?;
     mkdirp(&scripts_dir)?;
 
-    // layout.json
-    let layout_json = serde_json::to_string_pretty(layout)?;
+    // wallpapers
+    let wallpapers = bundle_wallpapers(
+        layout,
+        &opts.paths,
+        opts.source_bundle.as_deref(),
+        &bundle_dir.join("wallpapers"),
+    )?;
+
+    // layout.json, with the names the wallpapers were bundled under
+    let mut bundled = layout.clone();
+    for wp in bundled
+        .containments
+        .iter_mut()
+        .filter_map(|c| c.wallpaper.as_mut())
+    {
+        wp.bundled = wp
+            .files
+            .iter()
+            .filter_map(|f| Some((f.clone(), wallpapers.get(f)?.clone())))
+            .collect();
+    }
+    let layout_json = serde_json::to_string_pretty(&bundled)?;
     write_file(&bundle_dir.join("layout.json"), &layout_json, false)?;
 
+    let mut warnings = Vec::new();
+    for from in opts.screen_map.keys() {
+        if !layout.screens.connectors.values().any(|c| c == from) {
+            warnings.push(format!(
+                "--map-screen {from}: no screen in this layout uses that connector"
+            ));
+        }
+    }
+
+    if !layout.activities.is_empty() {
+        write_file(
+            &bundle_dir.join("activities.tsv"),
+            &scripts::activities_tsv(layout),
+            false,
+        )?;
+    }
+
+    let restore = scripts::RestoreOpts {
+        screen_map: opts.screen_map.clone(),
+        wallpapers,
+    };
+
     // scripts
     write_file(
         &scripts_dir.join("restore-layout.js"),
-        &scripts::restore_layout_js(layout),
+        &scripts::restore_layout_js(layout, &restore),
         false,
     )?;
     write_file(
@@ -129,44 +395,121 @@ pub fn export_bundle(layout: &Layout, opts: ExportOpts) -> Result<()> {
         true,
     )?;
 
+    // KWin settings and packages, restored by restore-kwin.sh
+    let kwin_dir = bundle_dir.join("kwin");
+    let mut kwin_packages = KWinPackages::default();
+    if let Some(kw) = &layout.kwin {
+        if opts.bundle_kwin_packages {
+            kwin_packages =
+                bundle_kwin_packages(kw, &opts.paths, opts.source_bundle.as_deref(), &kwin_dir)?;
+        }
+        write_file(
+            &scripts_dir.join("restore-kwin.sh"),
+            &scripts::restore_kwin_sh(kw, &kwin_packages),
+            true,
+        )?;
+    }
+
+    // KWin window rules, merged into the target's own by restore-kwin-rules.sh
+    let window_rules = layout
+        .kwin
+        .as_ref()
+        .map_or(&[][..], |k| &k.window_rules[..]);
+    if !window_rules.is_empty() {
+        mkdirp(&kwin_dir)?;
+        write_file(
+            &kwin_dir.join("kwinrulesrc"),
+            &kwin::kwinrules_fragment(window_rules),
+            false,
+        )?;
+        write_file(
+            &scripts_dir.join("restore-kwin-rules.sh"),
+            &scripts::restore_kwin_rules_sh(),
+            true,
+        )?;
+    }
+
     // snapshot
     if opts.snapshot {
         mkdirp(&snapshot_dir)?;
-        let src = PathBuf::from(&layout.source_file);
+        // Written from the parsed syntax tree: byte-identical to the source
+        // file unless the layout was edited in between.
         let dst = snapshot_dir.join("plasma-org.kde.plasma.desktop-appletsrc");
-        copy_file(&src, &dst)?;
+        write_file(&dst, &parser::write_appletsrc(layout), false)?;
     }
 
     // plasmoids bundle (user-installed only, by default)
     if opts.bundle_plasmoids {
         mkdirp(&plasmoids_dir)?;
         for pid in collect_plasmoid_ids(layout) {
+            // A re-exported bundle keeps the plasmoids it was made with.
+            let bundled = opts
+                .source_bundle
+                .as_ref()
+                .map(|b| b.join("plasmoids").join(&pid))
+                .filter(|p| p.is_dir());
+            if let Some(p) = bundled {
+                copy_dir_recursive(&p, &plasmoids_dir.join(&pid))?;
+                continue;
+            }
+
             // Prefer user-installed (this is what makes a layout portable)
-            if let Some(p) = user_plasmoid_dir(&pid) {
-                if p.is_dir() {
-                    let dst = plasmoids_dir.join(&pid);
-                    copy_dir_recursive(&p, &dst)?;
-                    continue;
-                }
+            let p = user_plasmoid_dir(&opts.paths, &pid);
+            if p.is_dir() {
+                let dst = plasmoids_dir.join(&pid);
+                copy_dir_recursive(&p, &dst)?;
+                continue;
             }
 
             // If not in user dir, do not bundle system plasmoids by default.
             // They should be installed by the OS. We leave them out intentionally.
             // (If you want, we can add a flag later to bundle system plasmoids too.)
-            let _ = system_plasmoid_dir(&pid);
+            let _ = system_plasmoid_dir(&opts.paths, &pid);
         }
     }
 
-    eprintln!("Exported bundle: {}", bundle_dir.display());
-    eprintln!(" - layout.json");
-    eprintln!(" - scripts/restore-portable.sh");
-    eprintln!(" - scripts/restore-snapshot.sh");
+    let mut contents = vec![
+        "layout.json".to_string(),
+        "scripts/restore-portable.sh".to_string(),
+        "scripts/restore-snapshot.sh".to_string(),
+    ];
+    if !layout.activities.is_empty() {
+        contents.push(format!(
+            "activities.tsv ({} activities)",
+            layout.activities.len()
+        ));
+    }
+    if layout.kwin.is_some() {
+        contents.push("scripts/restore-kwin.sh (kwinrc settings)".to_string());
+    }
+    if !kwin_packages.is_empty() {
+        contents.push(format!(
+            "kwin/ ({} KWin package(s) for restore-kwin.sh)",
+            kwin_packages.len()
+        ));
+    }
+    if !window_rules.is_empty() {
+        contents.push(format!(
+            "kwin/kwinrulesrc ({} window rule(s); scripts/restore-kwin-rules.sh)",
+            window_rules.len()
+        ));
+    }
+    if !restore.wallpapers.is_empty() {
+        contents.push(format!(
+            "wallpapers/ ({} file(s) or folder(s))",
+            restore.wallpapers.len()
+        ));
+    }
     if opts.snapshot {
-        eprintln!(" - snapshot/plasma-org.kde.plasma.desktop-appletsrc");
+        contents.push("snapshot/plasma-org.kde.plasma.desktop-appletsrc".to_string());
     }
     if opts.bundle_plasmoids {
-        eprintln!(" - plasmoids/ (user-installed plasmoids referenced by layout)");
+        contents.push("plasmoids/ (user-installed plasmoids referenced by layout)".to_string());
     }
 
-    Ok(())
+    Ok(Bundle {
+        dir: bundle_dir,
+        contents,
+        warnings,
+    })
 }
@@ -0,0 +1,177 @@
+use anyhow::{bail, Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::kconfig;
+use crate::kwin::load_kwin_info;
+use crate::model::Layout;
+use crate::parser::{self, APPLETSRC};
+use crate::paths::Paths;
+use crate::{activities, diagnostics, plasmashell, schema, screens};
+
+/// Suffixes of bundle archives; they are unpacked with the system `tar`.
+const ARCHIVES: &[&str] = &[
+    ".tar", ".tar.gz", ".tgz", ".tar.xz", ".txz", ".tar.zst", ".tar.bz2",
+];
+
+/// A layout and the bundle it was read from, if any.
+pub struct Loaded {
+    pub layout: Layout,
+
+    /// Bundle directory whose `wallpapers/` and `plasmoids/` an export can reuse
+    pub bundle_dir: Option<PathBuf>,
+
+    /// Keeps an unpacked archive on disk until the command is done
+    _unpacked: Option<tempfile::TempDir>,
+}
+
+impl From<Layout> for Loaded {
+    fn from(layout: Layout) -> Self {
+        Loaded {
+            layout,
+            bundle_dir: None,
+            _unpacked: None,
+        }
+    }
+}
+
+/// Config files a live layout is read from. `None` picks the file under the
+/// XDG config dirs, merged across the cascade (system defaults under the
+/// user file); paths given explicitly are read as-is.
+#[derive(Debug, Clone, Default)]
+pub struct ConfigFiles {
+    pub appletsrc: Option<String>,
+    pub kwinrc: Option<String>,
+    pub kwinrulesrc: Option<String>,
+    pub plasmashellrc: Option<String>,
+}
+
+/// Reads the layout, panel geometry, screens, activities and KWin info of
+/// the machine (or `--root`) described by `paths`.
+pub fn load_config(paths: &Paths, files: ConfigFiles) -> Result<Layout> {
+    let mut layout = match files.appletsrc {
+        Some(f) => parser::parse_appletsrc(&f)?,
+        None => parser::load_appletsrc(&paths.appletsrc(), &paths.config_dirs)?,
+    };
+
+    let kwin_dirs = if files.kwinrc.is_some() || files.kwinrulesrc.is_some() {
+        Vec::new()
+    } else {
+        paths.config_dirs.clone()
+    };
+    let kwinrc_path = files.kwinrc.unwrap_or_else(|| paths.kwinrc());
+    let kwinrules_path = files.kwinrulesrc.unwrap_or_else(|| paths.kwinrulesrc());
+    let data_dirs: Vec<PathBuf> = std::iter::once(paths.data_home.clone())
+        .chain(paths.data_dirs.iter().cloned())
+        .collect();
+    layout.kwin = load_kwin_info(
+        &kwinrc_path,
+        &kwinrules_path,
+        &kwin_dirs,
+        &data_dirs,
+        &mut layout.diagnostics,
+    )?;
+
+    let shell_dirs = if files.plasmashellrc.is_some() {
+        Vec::new()
+    } else {
+        paths.config_dirs.clone()
+    };
+    let plasmashellrc_path = files.plasmashellrc.unwrap_or_else(|| paths.plasmashellrc());
+    plasmashell::attach_plasmashellrc(&mut layout, &plasmashellrc_path, &shell_dirs)?;
+    screens::attach_kscreen_outputs(&mut layout, &paths.user_data("kscreen"))?;
+    activities::attach_activities(
+        &mut layout,
+        &paths.config_file("kactivitymanagerdrc"),
+        &paths.config_dirs,
+    )?;
+    diagnostics::sort(&mut layout.diagnostics);
+    Ok(layout)
+}
+
+/// Reads `--from`: an appletsrc, a layout.json, a bundle directory (or the
+/// `--out` directory holding one bundle), or a bundle archive.
+pub fn load(path: &Path) -> Result<Loaded> {
+    if path.is_dir() {
+        let dir = find_bundle(path)?;
+        return load_bundle(dir, None);
+    }
+    let name = path.to_string_lossy();
+    if ARCHIVES.iter().any(|s| name.ends_with(s)) {
+        let tmp = tempfile::Builder::new()
+            .prefix("kdesktop-copycat-")
+            .tempdir()
+            .context("creating a directory to unpack the bundle")?;
+        let status = Command::new("tar")
+            .arg("-xf")
+            .arg(path)
+            .arg("-C")
+            .arg(tmp.path())
+            .status()
+            .context("running tar")?;
+        if !status.success() {
+            bail!("unpacking {name}: tar exited with {status}");
+        }
+        let dir = find_bundle(tmp.path())?;
+        return load_bundle(dir, Some(tmp));
+    }
+
+    let text = fs::read_to_string(path).with_context(|| format!("reading {name}"))?;
+    if text.trim_start().starts_with('{') {
+        Ok(schema::load_layout_json(&text, &name)?.into())
+    } else {
+        let mut layout = parser::parse_appletsrc(&name)?;
+        diagnostics::sort(&mut layout.diagnostics);
+        Ok(layout.into())
+    }
+}
+
+/// `dir` itself if it holds a layout.json, else its only subdirectory that does.
+fn find_bundle(dir: &Path) -> Result<PathBuf> {
+    if dir.join("layout.json").is_file() {
+        return Ok(dir.to_path_buf());
+    }
+    let mut found: Vec<PathBuf> = fs::read_dir(dir)
+        .with_context(|| format!("reading {}", dir.display()))?
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|p| p.join("layout.json").is_file())
+        .collect();
+    found.sort();
+    match found.len() {
+        0 => bail!("{}: no layout.json found", dir.display()),
+        1 => Ok(found.remove(0)),
+        _ => bail!(
+            "{} holds {} bundles; pass one of them:\n  {}",
+            dir.display(),
+            found.len(),
+            found
+                .iter()
+                .map(|p| p.display().to_string())
+                .collect::<Vec<_>>()
+                .join("\n  ")
+        ),
+    }
+}
+
+/// Reads `dir/layout.json`. A snapshot in the bundle becomes the layout's
+/// syntax tree, so exporting again writes it back unchanged.
+fn load_bundle(dir: PathBuf, unpacked: Option<tempfile::TempDir>) -> Result<Loaded> {
+    let json = dir.join("layout.json");
+    let text = fs::read_to_string(&json).with_context(|| format!("reading {}", json.display()))?;
+    let mut layout = schema::load_layout_json(&text, &json.display().to_string())?;
+
+    let snapshot = dir.join("snapshot").join(APPLETSRC);
+    if snapshot.is_file() {
+        // Problems in the file were reported when the bundle was made.
+        let mut ignored = Vec::new();
+        layout.syntax = Some(kconfig::load(&snapshot.to_string_lossy(), &mut ignored)?);
+    }
+
+    Ok(Loaded {
+        layout,
+        bundle_dir: Some(dir),
+        _unpacked: unpacked,
+    })
+}
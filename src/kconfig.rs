@@ -0,0 +1,1227 @@
+//! KConfig rc file syntax shared by every scanner.
+//!
+//! Handles nested group paths (`[A][B]`), escapes, `[$i]`/`[$e]`/`[$d]`
+//! markers and localized keys, and keeps a lossless syntax tree so files
+//! can be written back with minimal changes.
+
+use anyhow::{Context, Result};
+use regex::Regex;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::LazyLock;
+
+use crate::diagnostics::{Diagnostic, DiagnosticKind};
+use crate::model::{Localized, Markers};
+
+/// Reads and parses the rc file at `path`; anomalies go to `diags`.
+pub(crate) fn load(path: &str, diags: &mut Vec<Diagnostic>) -> Result<IniTree> {
+    let raw = fs::read_to_string(path).with_context(|| format!("reading {path}"))?;
+    Ok(IniTree::parse(&raw, path, diags))
+}
+
+/// One rc file merged across the XDG cascade, the way KConfig reads it.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Cascade {
+    /// Effective values. Only the key/value view is meaningful; diagnostic
+    /// spans refer to the user file.
+    pub effective: IniTree,
+    /// The user file's own tree, the one edits are written back to
+    pub user: Option<IniTree>,
+    /// Raw text of the user file, for diagnostics
+    pub user_raw: String,
+    /// Files that were merged, least specific first
+    pub files: Vec<String>,
+    /// Group path → key (`key[locale]` for translations) → file that supplied the value
+    pub provenance: BTreeMap<Vec<String>, BTreeMap<String, String>>,
+}
+
+impl Cascade {
+    /// File that supplied `key` in the group at `path`, if it is not the user file.
+    pub(crate) fn non_user_source(&self, path: &[String], key: &str) -> Option<&String> {
+        let file = self.provenance.get(path)?.get(key)?;
+        (Some(file) != self.files.last()).then_some(file)
+    }
+}
+
+/// Loads `user_path` on top of `<dir>/<name>` for every dir in `config_dirs`
+/// (most important first, like `$XDG_CONFIG_DIRS`).
+///
+/// Later files override earlier ones, except where an earlier file locked a
+/// group or key with `[$i]`; `[$d]` removes an inherited key. If none of the
+/// files exist the cascade is empty (`files` is empty).
+pub(crate) fn load_cascade(
+    name: &str,
+    user_path: &str,
+    config_dirs: &[PathBuf],
+    diags: &mut Vec<Diagnostic>,
+) -> Result<Cascade> {
+    let mut cascade = Cascade::default();
+
+    let system: Vec<PathBuf> = config_dirs
+        .iter()
+        .rev()
+        .map(|d| d.join(name))
+        .filter(|p| p.is_file() && p != Path::new(user_path))
+        .collect();
+    for p in &system {
+        let file = p.display().to_string();
+        let tree = load(&file, diags)?;
+        cascade
+            .effective
+            .merge(&tree, &file, &mut cascade.provenance);
+        cascade.files.push(file);
+    }
+
+    if Path::new(user_path).exists() {
+        let raw = fs::read_to_string(user_path).with_context(|| format!("reading {user_path}"))?;
+        let tree = IniTree::parse(&raw, user_path, diags);
+        cascade
+            .effective
+            .merge(&tree, user_path, &mut cascade.provenance);
+        cascade.effective.value_spans = tree.value_spans.clone();
+        cascade.effective.header_lines = tree.header_lines.clone();
+        cascade.files.push(user_path.to_string());
+        cascade.user = Some(tree);
+        cascade.user_raw = raw;
+    }
+
+    Ok(cascade)
+}
+
+fn tokenize_section(section: &str) -> Vec<String> {
+    let mut out = Vec::new();
+    let mut cur = String::new();
+    let mut in_bracket = false;
+    for ch in section.chars() {
+        match ch {
+            '[' => {
+                in_bracket = true;
+                cur.clear();
+            }
+            ']' => {
+                if in_bracket {
+                    out.push(unescape(&cur));
+                    in_bracket = false;
+                }
+            }
+            _ => {
+                if in_bracket {
+                    cur.push(ch);
+                }
+            }
+        }
+    }
+    out
+}
+
+/// Which part of a line a string is escaped for; KConfig escapes a few
+/// extra characters in keys and group names.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Escape {
+    Key,
+    Value,
+    Group,
+}
+
+/// Decodes KConfig escapes (`\s`, `\t`, `\n`, `\r`, `\\`, `\xHH`).
+///
+/// Like KConfig, unknown escapes such as the list separators `\,` and `\;`
+/// are kept as-is so list values can still tell them from real separators.
+pub(crate) fn unescape(raw: &str) -> String {
+    let mut out = String::with_capacity(raw.len());
+    let mut bytes: Vec<u8> = Vec::new();
+    let mut chars = raw.chars().peekable();
+
+    while let Some(ch) = chars.next() {
+        if ch != '\\' {
+            flush_bytes(&mut bytes, &mut out);
+            out.push(ch);
+            continue;
+        }
+        match chars.peek().copied() {
+            Some('x') => {
+                chars.next();
+                let hex: String = chars.clone().take(2).collect();
+                // from_str_radix alone would take `+1`.
+                if hex.len() == 2 && hex.chars().all(|c| c.is_ascii_hexdigit()) {
+                    chars.nth(1);
+                    bytes.push(u8::from_str_radix(&hex, 16).unwrap());
+                } else {
+                    flush_bytes(&mut bytes, &mut out);
+                    out.push_str("\\x");
+                }
+                continue;
+            }
+            Some(c) => {
+                chars.next();
+                flush_bytes(&mut bytes, &mut out);
+                match c {
+                    's' => out.push(' '),
+                    't' => out.push('\t'),
+                    'n' => out.push('\n'),
+                    'r' => out.push('\r'),
+                    '\\' => out.push('\\'),
+                    other => {
+                        out.push('\\');
+                        out.push(other);
+                    }
+                }
+            }
+            None => {
+                flush_bytes(&mut bytes, &mut out);
+                out.push('\\');
+            }
+        }
+    }
+    flush_bytes(&mut bytes, &mut out);
+    out
+}
+
+/// `\xHH` escapes encode UTF-8 bytes; consecutive ones form one character.
+fn flush_bytes(bytes: &mut Vec<u8>, out: &mut String) {
+    if !bytes.is_empty() {
+        out.push_str(&String::from_utf8_lossy(bytes));
+        bytes.clear();
+    }
+}
+
+/// Encodes `s` the way KConfig writes it, so Plasma reads back the same string.
+pub(crate) fn escape(s: &str, kind: Escape) -> String {
+    let mut out = String::with_capacity(s.len());
+    for (i, ch) in s.chars().enumerate() {
+        match ch {
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            '\r' => out.push_str("\\r"),
+            ' ' if i == 0 && kind == Escape::Value => out.push_str("\\s"),
+            '=' if kind == Escape::Key => out.push_str("\\x3d"),
+            '[' | ']' if kind == Escape::Group => {
+                out.push_str(&format!("\\x{:02x}", ch as u32));
+            }
+            c if (c as u32) < 32 => out.push_str(&format!("\\x{:02x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    // Trailing whitespace would be trimmed on read.
+    if out.ends_with(' ') {
+        out.pop();
+        out.push_str("\\s");
+    }
+    out
+}
+
+/// One physical line of an rc file inside a group (or before the first group).
+///
+/// Everything we do not interpret is kept verbatim so an untouched tree
+/// serializes back to the exact bytes it was parsed from.
+#[derive(Debug, Clone)]
+enum Line {
+    /// Blank lines, comments and lines without `=`, including the line terminator.
+    Verbatim(String),
+    Entry {
+        key: String,
+        /// Locale of a localized key (`Name[de]=...`).
+        locale: Option<String>,
+        value: String,
+        /// `value` as written after the `=`, escapes and all.
+        value_raw: String,
+        /// KConfig option markers written after the key (`i`, `e`), re-emitted on edit.
+        options: String,
+        /// Original text without the terminator; `None` once the entry was edited.
+        raw: Option<String>,
+        eol: String,
+    },
+}
+
+impl Line {
+    fn write_to(&self, out: &mut String) {
+        match self {
+            Line::Verbatim(s) => out.push_str(s),
+            Line::Entry {
+                key,
+                locale,
+                value_raw,
+                options,
+                raw,
+                eol,
+                ..
+            } => {
+                match raw {
+                    Some(r) => out.push_str(r),
+                    None => {
+                        out.push_str(&escape(key, Escape::Key));
+                        if let Some(l) = locale {
+                            out.push_str(&format!("[{l}]"));
+                        }
+                        if !options.is_empty() {
+                            out.push_str(&format!("[${options}]"));
+                        }
+                        out.push('=');
+                        out.push_str(value_raw);
+                    }
+                }
+                out.push_str(eol);
+            }
+        }
+    }
+
+    fn ensure_newline(&mut self, nl: &str) {
+        match self {
+            Line::Verbatim(s) => {
+                if !s.ends_with('\n') {
+                    s.push_str(nl);
+                }
+            }
+            Line::Entry { eol, .. } => {
+                if eol.is_empty() {
+                    eol.push_str(nl);
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Group {
+    path: Vec<String>,
+    /// Header line as written, including the line terminator.
+    header: String,
+    lines: Vec<Line>,
+}
+
+/// Lossless concrete syntax tree of a KConfig rc file.
+///
+/// `sections` is the merged key/value view the model builder reads; `preamble`
+/// and `groups` keep every line in file order so [`IniTree::serialize`] can
+/// reproduce the input byte for byte, or with minimal edits after
+/// [`IniTree::set`] / [`IniTree::remove`].
+#[derive(Debug, Clone, Default)]
+pub struct IniTree {
+    preamble: Vec<Line>,
+    groups: Vec<Group>,
+    sections: BTreeMap<Vec<String>, BTreeMap<String, String>>,
+    localized: BTreeMap<Vec<String>, Localized>,
+    markers: BTreeMap<Vec<String>, Markers>,
+    /// Where each key's value starts: path → key → (line, column), 1-based.
+    value_spans: BTreeMap<Vec<String>, BTreeMap<String, (usize, usize)>>,
+    /// Line of the first header of each group, 1-based.
+    header_lines: BTreeMap<Vec<String>, usize>,
+    /// A lone `[$i]` before the first group locks the whole file.
+    file_immutable: bool,
+    /// Line terminator used for lines we add; follows the file's first line.
+    newline: Option<String>,
+}
+
+impl IniTree {
+    /// Parses `raw`, recovering from every anomaly and reporting it in `diags`.
+    pub(crate) fn parse(raw: &str, file: &str, diags: &mut Vec<Diagnostic>) -> IniTree {
+        // Only back-to-back `[...]` tokens form a header, so `[x]=[y]` is not one.
+        let header_re = Regex::new(r"^\s*((?:\[[^\]]*\])+)\s*$").unwrap();
+        let mut tree = IniTree::default();
+        // After a malformed header its keys are dropped instead of landing in the previous group.
+        let mut in_bad_group = false;
+        let mut seen: BTreeMap<(Vec<String>, String, Option<String>), usize> = BTreeMap::new();
+
+        for (idx, piece) in raw.split_inclusive('\n').enumerate() {
+            let lineno = idx + 1;
+            let (content, eol) = split_eol(piece);
+            let line = content.trim();
+            let col = content[..content.len() - content.trim_start().len()]
+                .chars()
+                .count()
+                + 1;
+            let width = line.chars().count();
+            if tree.newline.is_none() && !eol.is_empty() {
+                tree.newline = Some(eol.to_string());
+            }
+
+            if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+                tree.push_line(Line::Verbatim(piece.to_string()));
+                continue;
+            }
+            if line.starts_with('[') {
+                let mut toks = header_re
+                    .captures(line)
+                    .map(|cap| tokenize_section(cap.get(1).unwrap().as_str()))
+                    .unwrap_or_default();
+                let immutable = toks.last().map(String::as_str) == Some("$i");
+                if immutable {
+                    toks.pop();
+                }
+                if toks.is_empty() || toks.iter().any(String::is_empty) {
+                    if immutable && toks.is_empty() && tree.groups.is_empty() {
+                        tree.file_immutable = true;
+                    } else {
+                        diags.push(Diagnostic::new(
+                            DiagnosticKind::MalformedHeader,
+                            format!("malformed group header `{line}`; ignoring the group"),
+                            file,
+                            lineno,
+                            content,
+                            col,
+                            width,
+                        ));
+                        in_bad_group = true;
+                    }
+                    tree.push_line(Line::Verbatim(piece.to_string()));
+                    continue;
+                }
+                in_bad_group = false;
+                tree.sections.entry(toks.clone()).or_default();
+                tree.header_lines.entry(toks.clone()).or_insert(lineno);
+                if immutable {
+                    tree.markers.entry(toks.clone()).or_default().immutable = true;
+                }
+                tree.groups.push(Group {
+                    path: toks,
+                    header: piece.to_string(),
+                    lines: Vec::new(),
+                });
+                continue;
+            }
+
+            let path = tree.groups.last().map(|g| g.path.clone());
+            let path = match path {
+                Some(p) if !in_bad_group => p,
+                _ => {
+                    if !in_bad_group {
+                        diags.push(Diagnostic::new(
+                            DiagnosticKind::OrphanKey,
+                            "entry before the first group header is ignored",
+                            file,
+                            lineno,
+                            content,
+                            col,
+                            width,
+                        ));
+                    }
+                    tree.push_line(Line::Verbatim(piece.to_string()));
+                    continue;
+                }
+            };
+            match line.split_once('=') {
+                Some((k, v)) => {
+                    let (key, options) = split_key_options(k.trim());
+                    let (key, locale) = split_key_locale(key);
+                    let key = unescape(key);
+                    let value_raw = v.trim().to_string();
+                    let value = unescape(&value_raw);
+
+                    let key_width = k.trim_end().chars().count();
+                    let dup = (path.clone(), key.clone(), locale.clone());
+                    if let Some(first) = seen.insert(dup, lineno) {
+                        diags.push(Diagnostic::new(
+                            DiagnosticKind::DuplicateKey,
+                            format!(
+                                "duplicate key `{}` in {} (first set on line {first}); the last value wins",
+                                k.trim(),
+                                format_path(&path)
+                            ),
+                            file,
+                            lineno,
+                            content,
+                            col,
+                            key_width,
+                        ));
+                    }
+                    let value_col = col + line.find('=').map_or(0, |i| line[..=i].chars().count());
+                    tree.value_spans
+                        .entry(path.clone())
+                        .or_default()
+                        .insert(key.clone(), (lineno, value_col));
+
+                    let m = tree.markers.entry(path.clone()).or_default();
+                    if options.contains('i') {
+                        m.immutable_keys.insert(key.clone());
+                    }
+                    if options.contains('e') {
+                        m.expanded_keys.insert(key.clone());
+                    }
+                    m.deleted_keys.remove(&key);
+                    if locale.is_none() {
+                        if value_raw != escape(&value, Escape::Value) {
+                            m.raw_values.insert(key.clone(), value_raw.clone());
+                        } else {
+                            m.raw_values.remove(&key);
+                        }
+                    }
+                    tree.insert_value(path, &key, locale.as_deref(), &value);
+                    tree.push_line(Line::Entry {
+                        key,
+                        locale,
+                        value,
+                        value_raw,
+                        options,
+                        raw: Some(content.to_string()),
+                        eol: eol.to_string(),
+                    });
+                }
+                None => {
+                    // `key[$d]` deletes a value inherited from a less specific file.
+                    let (key, options) = split_key_options(line);
+                    if options.contains('d') {
+                        let (key, locale) = split_key_locale(key);
+                        let key = unescape(key);
+                        tree.remove_value(&path, &key, locale.as_deref());
+                        tree.markers
+                            .entry(path)
+                            .or_default()
+                            .deleted_keys
+                            .insert(key);
+                    } else {
+                        diags.push(Diagnostic::new(
+                            DiagnosticKind::MissingEquals,
+                            format!("expected `key=value` in {}", format_path(&path)),
+                            file,
+                            lineno,
+                            content,
+                            col,
+                            width,
+                        ));
+                    }
+                    tree.push_line(Line::Verbatim(piece.to_string()));
+                }
+            }
+        }
+
+        tree
+    }
+
+    fn newline(&self) -> String {
+        self.newline.clone().unwrap_or_else(|| "\n".to_string())
+    }
+
+    fn push_line(&mut self, line: Line) {
+        match self.groups.last_mut() {
+            Some(g) => g.lines.push(line),
+            None => self.preamble.push(line),
+        }
+    }
+
+    pub(crate) fn section(&self, path: &[String]) -> Option<&BTreeMap<String, String>> {
+        self.sections.get(path)
+    }
+
+    /// [`IniTree::section`] for a literal path, e.g. `group(&["Plugins"])`.
+    pub(crate) fn group(&self, path: &[&str]) -> Option<&BTreeMap<String, String>> {
+        let path: Vec<String> = path.iter().map(|s| s.to_string()).collect();
+        self.sections.get(&path)
+    }
+
+    /// Keys of the group at `path` in the order the file first sets them,
+    /// leaving out translations.
+    pub(crate) fn key_order(&self, path: &[&str]) -> Vec<String> {
+        let mut keys: Vec<String> = Vec::new();
+        for g in self.groups.iter().filter(|g| g.path == path) {
+            for line in &g.lines {
+                if let Line::Entry {
+                    key, locale: None, ..
+                } = line
+                {
+                    if !keys.contains(key) {
+                        keys.push(key.clone());
+                    }
+                }
+            }
+        }
+        keys
+    }
+
+    /// Line and column where the value of `key` starts.
+    pub(crate) fn value_span(&self, path: &[String], key: &str) -> Option<(usize, usize)> {
+        self.value_spans.get(path)?.get(key).copied()
+    }
+
+    /// Line of the first header of the group at `path`.
+    pub(crate) fn header_line(&self, path: &[String]) -> Option<usize> {
+        self.header_lines.get(path).copied()
+    }
+
+    pub(crate) fn localized(&self, path: &[String]) -> Localized {
+        self.localized.get(path).cloned().unwrap_or_default()
+    }
+
+    pub(crate) fn markers(&self, path: &[String]) -> Markers {
+        self.markers.get(path).cloned().unwrap_or_default()
+    }
+
+    /// True if the file, the group or any parent group carries `[$i]`.
+    pub(crate) fn is_immutable(&self, path: &[String]) -> bool {
+        self.file_immutable
+            || (1..=path.len()).any(|n| self.markers.get(&path[..n]).is_some_and(|m| m.immutable))
+    }
+
+    /// Applies `other` (a more specific file) on top of this tree, recording
+    /// which file supplied each value in `provenance`.
+    fn merge(
+        &mut self,
+        other: &IniTree,
+        file: &str,
+        provenance: &mut BTreeMap<Vec<String>, BTreeMap<String, String>>,
+    ) {
+        // Locks only bind files read later, so check them before taking
+        // `other`'s own markers.
+        let locked: BTreeMap<&Vec<String>, bool> = other
+            .sections
+            .keys()
+            .chain(other.markers.keys())
+            .map(|p| (p, self.is_immutable(p)))
+            .collect();
+        let key_locked = |me: &IniTree, path: &[String], key: &str| -> bool {
+            me.markers
+                .get(path)
+                .is_some_and(|m| m.immutable_keys.contains(key))
+        };
+
+        for (path, kv) in &other.sections {
+            if locked[path] {
+                continue;
+            }
+            self.sections.entry(path.clone()).or_default();
+            for (k, v) in kv {
+                if key_locked(self, path, k) {
+                    continue;
+                }
+                self.sections
+                    .get_mut(path)
+                    .unwrap()
+                    .insert(k.clone(), v.clone());
+                provenance
+                    .entry(path.clone())
+                    .or_default()
+                    .insert(k.clone(), file.to_string());
+                let mine = self.markers.entry(path.clone()).or_default();
+                take_key_markers(mine, other.markers.get(path), k);
+            }
+        }
+        for (path, keys) in &other.localized {
+            if locked[path] {
+                continue;
+            }
+            for (k, locales) in keys {
+                if key_locked(self, path, k) {
+                    continue;
+                }
+                for (l, v) in locales {
+                    self.insert_value(path.clone(), k, Some(l), v);
+                    provenance
+                        .entry(path.clone())
+                        .or_default()
+                        .insert(format!("{k}[{l}]"), file.to_string());
+                }
+            }
+        }
+        for (path, m) in &other.markers {
+            if locked[path] {
+                continue;
+            }
+            for k in &m.deleted_keys {
+                if key_locked(self, path, k) {
+                    continue;
+                }
+                self.remove_value(path, k, None);
+                if let Some(p) = provenance.get_mut(path) {
+                    p.remove(k);
+                }
+                let mine = self.markers.entry(path.clone()).or_default();
+                take_key_markers(mine, Some(m), k);
+                mine.deleted_keys.insert(k.clone());
+            }
+            self.markers.entry(path.clone()).or_default().immutable |= m.immutable;
+        }
+        self.file_immutable |= other.file_immutable;
+    }
+
+    pub(crate) fn iter_sections(
+        &self,
+    ) -> impl Iterator<Item = (&Vec<String>, &BTreeMap<String, String>)> {
+        self.sections.iter()
+    }
+
+    fn value(&self, path: &[String], key: &str, locale: Option<&str>) -> Option<&String> {
+        match locale {
+            None => self.sections.get(path)?.get(key),
+            Some(l) => self.localized.get(path)?.get(key)?.get(l),
+        }
+    }
+
+    fn insert_value(&mut self, path: Vec<String>, key: &str, locale: Option<&str>, value: &str) {
+        match locale {
+            None => {
+                self.sections
+                    .entry(path)
+                    .or_default()
+                    .insert(key.to_string(), value.to_string());
+            }
+            Some(l) => {
+                self.localized
+                    .entry(path)
+                    .or_default()
+                    .entry(key.to_string())
+                    .or_default()
+                    .insert(l.to_string(), value.to_string());
+            }
+        }
+    }
+
+    fn remove_value(&mut self, path: &[String], key: &str, locale: Option<&str>) {
+        match locale {
+            None => {
+                if let Some(s) = self.sections.get_mut(path) {
+                    s.remove(key);
+                }
+            }
+            Some(l) => {
+                if let Some(loc) = self.localized.get_mut(path) {
+                    if let Some(m) = loc.get_mut(key) {
+                        m.remove(l);
+                        if m.is_empty() {
+                            loc.remove(key);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Sets `key` in the group at `path`, editing the existing line in place,
+    /// appending to the group, or creating the group at the end of the file.
+    ///
+    /// New lines get the `[$i]`/`[$e]` options `markers` has for `key`, and
+    /// the value is written the way `markers` recorded it (see [`written_value`]).
+    /// A group created here gets `[$i]` if `markers` locks the whole group.
+    pub fn set(&mut self, path: &[String], key: &str, value: &str, markers: &Markers) {
+        let options: String = [
+            ('i', markers.immutable_keys.contains(key)),
+            ('e', markers.expanded_keys.contains(key)),
+        ]
+        .into_iter()
+        .filter_map(|(c, on)| on.then_some(c))
+        .collect();
+        let value_raw = written_value(key, value, markers);
+        if markers.immutable && !self.groups.iter().any(|g| g.path == path) {
+            self.push_group(path, true);
+        }
+        self.set_entry(path, key, None, value, value_raw, &options);
+    }
+
+    /// Like [`IniTree::set`] for the `locale` variant of `key` (`key[locale]=`).
+    pub fn set_localized(&mut self, path: &[String], key: &str, locale: &str, value: &str) {
+        let value_raw = escape(value, Escape::Value);
+        self.set_entry(path, key, Some(locale), value, value_raw, "");
+    }
+
+    fn set_entry(
+        &mut self,
+        path: &[String],
+        key: &str,
+        locale: Option<&str>,
+        value: &str,
+        value_raw: String,
+        options: &str,
+    ) {
+        if self.value(path, key, locale).map(String::as_str) == Some(value) {
+            return;
+        }
+        self.insert_value(path.to_vec(), key, locale, value);
+        if !self.sections.contains_key(path) {
+            self.sections.insert(path.to_vec(), BTreeMap::new());
+        }
+
+        // Edit the last occurrence: that is the one KConfig reads.
+        for g in self.groups.iter_mut().rev().filter(|g| g.path == path) {
+            for line in g.lines.iter_mut().rev() {
+                if let Line::Entry {
+                    key: k,
+                    locale: l,
+                    value: v,
+                    value_raw: vr,
+                    raw,
+                    ..
+                } = line
+                {
+                    if k == key && l.as_deref() == locale {
+                        *v = value.to_string();
+                        *vr = value_raw;
+                        *raw = None;
+                        return;
+                    }
+                }
+            }
+        }
+
+        let entry = Line::Entry {
+            key: key.to_string(),
+            locale: locale.map(str::to_string),
+            value: value.to_string(),
+            value_raw,
+            options: options.to_string(),
+            raw: None,
+            eol: self.newline(),
+        };
+        if !self.groups.iter().any(|g| g.path == path) {
+            self.push_group(path, false);
+        }
+        let nl = self.newline();
+        let g = self
+            .groups
+            .iter_mut()
+            .rev()
+            .find(|g| g.path == path)
+            .unwrap();
+        // Insert after the last entry so trailing blank lines keep separating groups.
+        let at = g
+            .lines
+            .iter()
+            .rposition(|l| matches!(l, Line::Entry { .. }))
+            .map(|i| i + 1)
+            .unwrap_or(0);
+        if at > 0 {
+            g.lines[at - 1].ensure_newline(&nl);
+        } else if !g.header.ends_with('\n') {
+            g.header.push_str(&nl);
+        }
+        g.lines.insert(at, entry);
+    }
+
+    /// Removes every occurrence of `key` from the group at `path`.
+    pub fn remove(&mut self, path: &[String], key: &str) {
+        self.remove_entry(path, key, None);
+    }
+
+    /// Removes the `locale` variant of `key` from the group at `path`.
+    pub fn remove_localized(&mut self, path: &[String], key: &str, locale: &str) {
+        self.remove_entry(path, key, Some(locale));
+    }
+
+    fn remove_entry(&mut self, path: &[String], key: &str, locale: Option<&str>) {
+        self.remove_value(path, key, locale);
+        for g in self.groups.iter_mut().filter(|g| g.path == path) {
+            g.lines.retain(|line| {
+                !matches!(line, Line::Entry { key: k, locale: l, .. }
+                    if k == key && l.as_deref() == locale)
+            });
+        }
+    }
+
+    /// Removes every group for which `pred` returns true, header and lines alike.
+    pub(crate) fn remove_groups(&mut self, pred: impl Fn(&[String]) -> bool) {
+        self.groups.retain(|g| !pred(&g.path));
+        self.sections.retain(|p, _| !pred(p));
+        self.localized.retain(|p, _| !pred(p));
+    }
+
+    /// Appends a header for `path`, marked `[$i]` if `immutable`.
+    fn push_group(&mut self, path: &[String], immutable: bool) {
+        let nl = self.newline();
+        let last = self
+            .groups
+            .last_mut()
+            .map(|g| &mut g.lines)
+            .unwrap_or(&mut self.preamble);
+        if let Some(l) = last.last_mut() {
+            l.ensure_newline(&nl);
+        }
+        let needs_blank = match last.last() {
+            Some(Line::Verbatim(s)) => !s.trim().is_empty(),
+            Some(Line::Entry { .. }) => true,
+            None => false,
+        };
+        if needs_blank {
+            last.push(Line::Verbatim(nl.clone()));
+        }
+        if let Some(g) = self.groups.last_mut() {
+            if g.lines.is_empty() && !g.header.ends_with('\n') {
+                g.header.push_str(&nl);
+            }
+        }
+
+        let header = path
+            .iter()
+            .map(|p| format!("[{}]", escape(p, Escape::Group)))
+            .collect::<String>()
+            + if immutable { "[$i]" } else { "" }
+            + &nl;
+        self.sections.entry(path.to_vec()).or_default();
+        if immutable {
+            self.markers.entry(path.to_vec()).or_default().immutable = true;
+        }
+        self.groups.push(Group {
+            path: path.to_vec(),
+            header,
+            lines: Vec::new(),
+        });
+    }
+
+    /// Writes the tree back out. Unedited lines are reproduced byte for byte.
+    pub fn serialize(&self) -> String {
+        let mut out = String::new();
+        for l in &self.preamble {
+            l.write_to(&mut out);
+        }
+        for g in &self.groups {
+            out.push_str(&g.header);
+            for l in &g.lines {
+                l.write_to(&mut out);
+            }
+        }
+        out
+    }
+}
+
+/// `[Containments][1]` style rendering of a group path, for messages.
+pub(crate) fn format_path(path: &[String]) -> String {
+    path.iter().map(|p| format!("[{p}]")).collect()
+}
+
+/// Splits `key[$ie]` into the key and its option letters.
+fn split_key_options(key: &str) -> (&str, String) {
+    let mut key = key;
+    let mut options = String::new();
+    while let Some(rest) = key.strip_suffix(']') {
+        let Some(open) = rest.rfind('[') else { break };
+        let Some(opts) = rest[open + 1..].strip_prefix('$') else {
+            break;
+        };
+        options.insert_str(0, opts);
+        key = rest[..open].trim_end();
+    }
+    (key, options)
+}
+
+/// Splits `Name[de_DE]` into the key and its locale.
+fn split_key_locale(key: &str) -> (&str, Option<String>) {
+    if let Some(rest) = key.strip_suffix(']') {
+        if let Some(open) = rest.rfind('[') {
+            if open > 0 {
+                return (rest[..open].trim_end(), Some(rest[open + 1..].to_string()));
+            }
+        }
+    }
+    (key, None)
+}
+
+/// Gives `key` the per-key markers it has in `theirs`, the markers of the
+/// file its value now comes from, dropping whatever `mine` had for it.
+fn take_key_markers(mine: &mut Markers, theirs: Option<&Markers>, key: &str) {
+    let none = Markers::default();
+    let theirs = theirs.unwrap_or(&none);
+    for (mine, theirs) in [
+        (&mut mine.immutable_keys, &theirs.immutable_keys),
+        (&mut mine.expanded_keys, &theirs.expanded_keys),
+    ] {
+        if theirs.contains(key) {
+            mine.insert(key.to_string());
+        } else {
+            mine.remove(key);
+        }
+    }
+    match theirs.raw_values.get(key) {
+        Some(raw) => mine.raw_values.insert(key.to_string(), raw.clone()),
+        None => mine.raw_values.remove(key),
+    };
+    mine.deleted_keys.remove(key);
+}
+
+/// `value` of `key` the way `markers` recorded it in the original file, or
+/// escaped the way KConfig writes it when that form no longer decodes to
+/// `value`.
+pub(crate) fn written_value(key: &str, value: &str, markers: &Markers) -> String {
+    markers
+        .raw_values
+        .get(key)
+        .filter(|r| unescape(r) == value)
+        .cloned()
+        .unwrap_or_else(|| escape(value, Escape::Value))
+}
+
+static ENV_VAR: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"\$(\$|\{([A-Za-z_][A-Za-z0-9_]*)\}|([A-Za-z_][A-Za-z0-9_]*))").unwrap()
+});
+
+/// `[$e]` expansion: `$VAR` and `${VAR}` from the environment, `$$` for a
+/// literal dollar. `$(command)` is left as written; we never run commands.
+///
+/// Values are stored unexpanded; this is only for showing them and for
+/// finding the files they point at on this machine.
+pub(crate) fn expand_env(value: &str) -> String {
+    ENV_VAR
+        .replace_all(value, |c: &regex::Captures| {
+            if &c[1] == "$" {
+                return "$".to_string();
+            }
+            let name = c.get(2).or_else(|| c.get(3)).unwrap().as_str();
+            std::env::var(name).unwrap_or_default()
+        })
+        .into_owned()
+}
+
+fn split_eol(piece: &str) -> (&str, &str) {
+    if let Some(s) = piece.strip_suffix("\r\n") {
+        (s, "\r\n")
+    } else if let Some(s) = piece.strip_suffix('\n') {
+        (s, "\n")
+    } else {
+        (piece, "")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(raw: &str) -> IniTree {
+        let mut diags = Vec::new();
+        let tree = IniTree::parse(raw, "rc", &mut diags);
+        assert!(diags.is_empty(), "{diags:?}");
+        tree
+    }
+
+    fn path(p: &[&str]) -> Vec<String> {
+        p.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn parses_markers_into_flags() {
+        let tree =
+            parse("[G][$i]\na=1\n\n[G][Sub]\nb=2\n\n[H]\nkey[$i]=x\npath[$e]=$HOME/x\ngone[$d]\n");
+        // `$i` is a flag, not a path component.
+        assert!(tree.section(&path(&["G", "$i"])).is_none());
+        assert_eq!(tree.section(&path(&["G"])).unwrap()["a"], "1");
+        assert!(tree.is_immutable(&path(&["G"])));
+        assert!(tree.is_immutable(&path(&["G", "Sub"])));
+        assert!(!tree.is_immutable(&path(&["H"])));
+
+        let h = path(&["H"]);
+        let kv = tree.section(&h).unwrap();
+        assert_eq!(kv["key"], "x");
+        assert_eq!(kv["path"], "$HOME/x");
+        assert!(!kv.contains_key("gone"));
+        let m = tree.markers(&h);
+        assert!(m.is_key_locked("key") && !m.is_key_locked("path"));
+        assert!(m.expanded_keys.contains("path"));
+        assert!(m.deleted_keys.contains("gone"));
+
+        // `[$i]` before the first group locks the whole file.
+        assert!(parse("[$i]\n[H]\nx=1\n").is_immutable(&h));
+    }
+
+    #[test]
+    fn expanded_values_keep_their_marker() {
+        let mut tree = parse("[G]\npath[$e]=$HOME/x\n");
+        let g = path(&["G"]);
+        let markers = tree.markers(&g);
+        tree.set(&g, "path", "$HOME/y", &markers);
+        assert_eq!(tree.serialize(), "[G]\npath[$e]=$HOME/y\n");
+    }
+
+    #[test]
+    fn localized_keys_map_locale_to_value() {
+        let mut tree = parse("[G]\nName=Start\nName[de]=Startmenü\nName[fr_FR]=Démarrer\n");
+        let g = path(&["G"]);
+        let kv = tree.section(&g).unwrap();
+        assert_eq!(kv.keys().collect::<Vec<_>>(), ["Name"]);
+        let names = &tree.localized(&g)["Name"];
+        assert_eq!(names["de"], "Startmenü");
+        assert_eq!(names["fr_FR"], "Démarrer");
+
+        tree.set_localized(&g, "Name", "de", "Anwendungen");
+        tree.remove_localized(&g, "Name", "fr_FR");
+        tree.set_localized(&g, "Name", "sv", "Meny");
+        assert_eq!(
+            tree.serialize(),
+            "[G]\nName=Start\nName[de]=Anwendungen\nName[sv]=Meny\n"
+        );
+    }
+
+    #[test]
+    fn reports_anomalies_and_recovers() {
+        let mut diags = Vec::new();
+        let tree = IniTree::parse(
+            "orphan=1\n[G\nlost=1\n[H]\n  a=1\n  a=2\n  junk\nb=3\n",
+            "rc",
+            &mut diags,
+        );
+        let found: Vec<_> = diags
+            .iter()
+            .map(|d| (d.kind, d.line, d.column, d.len))
+            .collect();
+        assert_eq!(
+            found,
+            [
+                (DiagnosticKind::OrphanKey, 1, 1, 8),
+                (DiagnosticKind::MalformedHeader, 2, 1, 2),
+                (DiagnosticKind::DuplicateKey, 6, 3, 1),
+                (DiagnosticKind::MissingEquals, 7, 3, 4),
+            ]
+        );
+        assert_eq!(diags[3].snippet, "  junk");
+
+        // Keys under the bad header are dropped; parsing goes on after it.
+        assert_eq!(tree.iter_sections().count(), 1);
+        let h = path(&["H"]);
+        let kv = tree.section(&h).unwrap();
+        assert_eq!((&kv["a"][..], &kv["b"][..]), ("2", "3"));
+        assert_eq!(tree.value_span(&h, "a"), Some((6, 5)));
+    }
+
+    /// Loads `user` on top of `system` files (most important first), all
+    /// written below `dir`.
+    fn cascade(dir: &Path, system: &[&str], user: &str) -> (Cascade, Vec<String>) {
+        let mut dirs = Vec::new();
+        for (n, text) in system.iter().enumerate() {
+            let d = dir.join(format!("xdg{n}"));
+            fs::create_dir_all(&d).unwrap();
+            fs::write(d.join("testrc"), text).unwrap();
+            dirs.push(d);
+        }
+        let user_path = dir.join("testrc");
+        fs::write(&user_path, user).unwrap();
+        let mut diags = Vec::new();
+        let c = load_cascade("testrc", user_path.to_str().unwrap(), &dirs, &mut diags).unwrap();
+        assert!(diags.is_empty(), "{diags:?}");
+        let files = dirs
+            .iter()
+            .map(|d| d.join("testrc").display().to_string())
+            .chain([user_path.display().to_string()])
+            .collect();
+        (c, files)
+    }
+
+    #[test]
+    fn user_file_overrides_system_defaults() {
+        let dir = tempfile::tempdir().unwrap();
+        let (c, files) = cascade(
+            dir.path(),
+            &["[G]\na=first\n", "[G]\na=second\nb=second\nc=second\n"],
+            "[G]\nc=user\n",
+        );
+        let g = path(&["G"]);
+        let kv = c.effective.section(&g).unwrap();
+        assert_eq!(
+            (&kv["a"][..], &kv["b"][..], &kv["c"][..]),
+            ("first", "second", "user")
+        );
+        // Least specific first, user file last.
+        assert_eq!(
+            c.files,
+            [files[1].clone(), files[0].clone(), files[2].clone()]
+        );
+        assert_eq!(c.non_user_source(&g, "a"), Some(&files[0]));
+        assert_eq!(c.non_user_source(&g, "b"), Some(&files[1]));
+        assert_eq!(c.non_user_source(&g, "c"), None);
+    }
+
+    #[test]
+    fn system_locks_beat_the_user_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let (c, files) = cascade(
+            dir.path(),
+            &["[G]\nkey[$i]=system\n\n[H][$i]\nx=system\n"],
+            "[G]\nkey=user\nfree=user\n\n[H]\nx=user\ny=user\n",
+        );
+        let g = c.effective.section(&path(&["G"])).unwrap();
+        assert_eq!(g["key"], "system");
+        assert_eq!(g["free"], "user");
+        let h = c.effective.section(&path(&["H"])).unwrap();
+        assert_eq!(h["x"], "system");
+        assert!(!h.contains_key("y"));
+        assert_eq!(c.non_user_source(&path(&["G"]), "key"), Some(&files[0]));
+        assert!(c.effective.is_immutable(&path(&["H"])));
+    }
+
+    #[test]
+    fn markers_come_from_the_file_that_supplies_the_value() {
+        let dir = tempfile::tempdir().unwrap();
+        let (c, _) = cascade(
+            dir.path(),
+            &["[G]\nicon[$e]=$HOME/sys\nodd=\\x41\nkept[$e]=$HOME/k\nkey[$i]=system\n"],
+            "[G]\nicon=/plain\nodd=B\nkey[$e]=$HOME/user\n",
+        );
+        let g = path(&["G"]);
+        let kv = c.effective.section(&g).unwrap();
+        assert_eq!((&kv["icon"][..], &kv["key"][..]), ("/plain", "system"));
+        let m = c.effective.markers(&g);
+        assert_eq!(m.expanded_keys, ["kept".to_string()].into());
+        assert_eq!(m.immutable_keys, ["key".to_string()].into());
+        assert!(m.raw_values.is_empty(), "{:?}", m.raw_values);
+    }
+
+    #[test]
+    fn new_locked_group_is_written_with_its_marker() {
+        let g = path(&["G"]);
+        let markers = Markers {
+            immutable: true,
+            ..Markers::default()
+        };
+        let mut tree = IniTree::default();
+        tree.set(&g, "a", "1", &markers);
+        tree.set(&g, "b", "2", &markers);
+        assert_eq!(tree.serialize(), "[G][$i]\na=1\nb=2\n");
+        assert!(parse(&tree.serialize()).is_immutable(&g));
+    }
+
+    #[test]
+    fn deleted_keys_drop_inherited_values() {
+        let dir = tempfile::tempdir().unwrap();
+        let (c, _) = cascade(
+            dir.path(),
+            &["[G]\nold=system\nkept=system\n"],
+            "[G]\nold[$d]\n",
+        );
+        let g = path(&["G"]);
+        let kv = c.effective.section(&g).unwrap();
+        assert!(!kv.contains_key("old"));
+        assert_eq!(kv["kept"], "system");
+        assert!(!c.provenance[&g].contains_key("old"));
+        assert!(c.effective.markers(&g).deleted_keys.contains("old"));
+    }
+
+    #[test]
+    fn unescapes_kconfig_escapes() {
+        assert_eq!(unescape(r"a\sb\tc\nd\re\\f"), "a b\tc\nd\re\\f");
+        assert_eq!(unescape(r"\x41\xc3\xa9"), "Aé");
+        // Unknown escapes and broken `\x` stay as written.
+        assert_eq!(unescape(r"a\,b\;c"), r"a\,b\;c");
+        assert_eq!(unescape(r"\xZZ"), r"\xZZ");
+        assert_eq!(unescape(r"\x+1"), r"\x+1");
+        assert_eq!(unescape(r"a\x4"), r"a\x4");
+        assert_eq!(unescape("end\\"), "end\\");
+    }
+
+    #[test]
+    fn escapes_like_kconfig_writes() {
+        assert_eq!(escape("a\tb\nc\rd\\e", Escape::Value), r"a\tb\nc\rd\\e");
+        assert_eq!(escape("\u{1}", Escape::Value), r"\x01");
+        // Only a leading or trailing space needs `\s`.
+        assert_eq!(escape(" a b ", Escape::Value), r"\sa b\s");
+        assert_eq!(escape(" a", Escape::Key), " a");
+        assert_eq!(escape("a=b", Escape::Key), r"a\x3db");
+        assert_eq!(escape("[x]", Escape::Group), r"\x5bx\x5d");
+        for s in [" lead", "trail ", "tab\there", "back\\slash", "é"] {
+            assert_eq!(unescape(&escape(s, Escape::Value)), s);
+        }
+    }
+
+    #[test]
+    fn keeps_the_value_as_written() {
+        let tree = parse("[G]\nodd=\\x41b\nplain=\\sa\n");
+        let g = path(&["G"]);
+        assert_eq!(tree.section(&g).unwrap()["odd"], "Ab");
+        let markers = tree.markers(&g);
+        assert_eq!(markers.raw_values["odd"], r"\x41b");
+        // Canonical escaping is not recorded.
+        assert!(!markers.raw_values.contains_key("plain"));
+
+        let mut fresh = IniTree::default();
+        fresh.set(&g, "odd", "Ab", &markers);
+        fresh.set(&g, "plain", " a", &markers);
+        assert_eq!(fresh.serialize(), "[G]\nodd=\\x41b\nplain=\\sa\n");
+
+        // A changed value is escaped the usual way.
+        fresh.set(&g, "odd", "Ac ", &markers);
+        assert_eq!(fresh.serialize(), "[G]\nodd=Ac\\s\nplain=\\sa\n");
+    }
+}
@@ -1,126 +1,478 @@
-use anyhow::{Context, Result};
-use regex::Regex;
+use anyhow::Result;
 use std::collections::BTreeMap;
 use std::fs;
+use std::path::{Path, PathBuf};
 
-use crate::model::{KWinScan, KWinSummary};
+use crate::diagnostics::Diagnostic;
+use crate::kconfig::{self, Escape, IniTree};
+use crate::model::{
+    ConfigEnum, KWinPlugin, KWinPluginKind, KWinScan, KWinSummary, MatchKind, RulePolicy,
+    RuleProperty, VirtualDesktop, VirtualDesktops, WindowMatch, WindowRule,
+};
+use crate::parser::split_list_raw;
 
-#[derive(Debug, Default)]
-struct Ini {
-    sections: BTreeMap<String, BTreeMap<String, String>>,
-}
+/// kwinrulesrc keys that say which windows a rule matches, as
+/// (value key, match-type key).
+const MATCH_KEYS: [(&str, &str); 4] = [
+    ("wmclass", "wmclassmatch"),
+    ("title", "titlematch"),
+    ("windowrole", "windowrolematch"),
+    ("clientmachine", "clientmachinematch"),
+];
 
-impl Ini {
-    fn get_section(&self, name: &str) -> Option<&BTreeMap<String, String>> {
-        self.sections.get(name)
-    }
-}
+/// kwinrc group selecting the window decoration.
+pub const DECORATION: &str = "org.kde.kdecoration2";
 
-fn parse_ini(path: &str) -> Result<Ini> {
-    let raw = fs::read_to_string(path).with_context(|| format!("reading {path}"))?;
-    let header_re = Regex::new(r"^\s*\[(.+?)\]\s*$")?;
+/// Decoration `library` of Aurorae, which draws SVG and QML themes.
+pub const AURORAE: &str = "org.kde.kwin.aurorae";
 
-    let mut ini = Ini::default();
-    let mut cur: Option<String> = None;
+/// Prefix of Aurorae SVG theme names in `theme=`; QML themes use their package id.
+pub const AURORAE_SVG_PREFIX: &str = "__aurorae__svg__";
 
-    for line in raw.lines() {
-        let line = line.trim();
-        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
-            continue;
-        }
+/// Effects compiled into KWin (Plasma 6), which have no metadata.json on
+/// disk: id, name and whether KWin enables them by default.
+const BUILTIN_EFFECTS: &[(&str, &str, bool)] = &[
+    ("backgroundcontrast", "Background Contrast", true),
+    ("blendchanges", "Blend", true),
+    ("blur", "Blur", true),
+    (
+        "colorblindnesscorrection",
+        "Color Blindness Correction",
+        false,
+    ),
+    ("colorpicker", "Color Picker", true),
+    ("diminactive", "Dim Inactive", false),
+    ("fallapart", "Fall Apart", false),
+    ("glide", "Glide", false),
+    ("hidecursor", "Hide Cursor", false),
+    ("highlightwindow", "Highlight Window", true),
+    ("invert", "Invert", false),
+    ("kscreen", "Kscreen", true),
+    ("magiclamp", "Magic Lamp", false),
+    ("magnifier", "Magnifier", false),
+    ("mouseclick", "Mouse Click Animation", false),
+    ("mousemark", "Mouse Mark", false),
+    ("outputlocator", "Output Locator", true),
+    ("overview", "Overview", true),
+    ("screenedge", "Screen Edge", true),
+    ("screenshot", "Screenshot", true),
+    ("screentransform", "Rotation", true),
+    ("shakecursor", "Shake Cursor", true),
+    ("showpaint", "Show Paint", false),
+    ("slide", "Slide", true),
+    ("slideback", "Slide Back", false),
+    ("slidingpopups", "Sliding Popups", true),
+    ("snaphelper", "Snap Helper", false),
+    ("startupfeedback", "Startup Feedback", true),
+    ("systembell", "System Bell", false),
+    ("thumbnailaside", "Thumbnail Aside", false),
+    ("tileseditor", "Tiles Editor", true),
+    ("touchpoints", "Touch Points", false),
+    ("trackmouse", "Track Mouse", false),
+    ("windowview", "Window View", true),
+    ("wobblywindows", "Wobbly Windows", false),
+    ("zoom", "Zoom", true),
+];
 
-        if let Some(c) = header_re.captures(line) {
-            cur = Some(c.get(1).unwrap().as_str().to_string());
-            continue;
-        }
+/// Tier-1 scan:
+/// - effects/scripts switched on or off in `[Plugins]` (`<id>Enabled=`),
+///   classified by their package metadata under `data_dirs`
+/// - task switcher from `[TabBox]`, `[TabBoxAlternative]`
+/// - window decoration from `[org.kde.kdecoration2]`
+/// - virtual desktops from `[Desktops]`
+/// - window rules from kwinrulesrc, in `[General] rules=` order
+///
+/// Both files are read through the XDG cascade over `config_dirs` (pass an
+/// empty slice to read exactly the given files). `data_dirs` are searched
+/// for `kwin/effects` and `kwin/scripts` packages, most important first.
+/// Returns `None` if no kwinrc exists anywhere. Parse anomalies are
+/// appended to `diags`.
+pub fn load_kwin_info(
+    kwinrc_path: &str,
+    kwinrulesrc_path: &str,
+    config_dirs: &[PathBuf],
+    data_dirs: &[PathBuf],
+    diags: &mut Vec<Diagnostic>,
+) -> Result<Option<KWinScan>> {
+    let mut summary = KWinSummary::default();
+    let mut provenance = BTreeMap::new();
 
-        let Some(sec) = cur.clone() else {
+    // kwinrc
+    let kwinrc = kconfig::load_cascade("kwinrc", kwinrc_path, config_dirs, diags)?;
+    if kwinrc.files.is_empty() {
+        return Ok(None);
+    }
+    let ini = &kwinrc.effective;
+
+    let packages = kwin_packages(data_dirs);
+    let mut plugins = Vec::new();
+    for (k, v) in ini.group(&["Plugins"]).into_iter().flatten() {
+        // e.g. "blurEnabled=true", "kwin4_effect_translucencyEnabled=false"
+        let Some(id) = k.strip_suffix("Enabled").filter(|id| !id.is_empty()) else {
             continue;
         };
-        if let Some((k, v)) = line.split_once('=') {
-            ini.sections
-                .entry(sec)
-                .or_default()
-                .insert(k.trim().to_string(), v.trim().to_string());
+        let enabled = match v.trim() {
+            v if v.eq_ignore_ascii_case("true") => true,
+            v if v.eq_ignore_ascii_case("false") => false,
+            _ => continue,
+        };
+        let plugin = resolve_plugin(id, enabled, &packages);
+        let list = match (plugin.kind, enabled) {
+            (Some(KWinPluginKind::Effect), true) => &mut summary.enabled_effects,
+            (Some(KWinPluginKind::Effect), false) => &mut summary.disabled_effects,
+            (Some(KWinPluginKind::Script), true) => &mut summary.enabled_scripts,
+            (Some(KWinPluginKind::Script), false) => &mut summary.disabled_scripts,
+            (None, _) => &mut summary.unknown_plugins,
+        };
+        list.push(id.to_string());
+        plugins.push(plugin);
+    }
+
+    if let Some(tabbox) = ini.group(&["TabBox"]) {
+        summary.task_switcher = tabbox.clone();
+    }
+    if let Some(tabbox_alt) = ini.group(&["TabBoxAlternative"]) {
+        summary.task_switcher_alternative = tabbox_alt.clone();
+    }
+    if let Some(decoration) = ini.group(&[DECORATION]) {
+        summary.decoration = decoration.clone();
+    }
+    summary.virtual_desktops = ini.group(&["Desktops"]).map(parse_desktops);
+
+    for group in [
+        "Plugins",
+        "TabBox",
+        "TabBoxAlternative",
+        "Desktops",
+        DECORATION,
+    ] {
+        let path = vec![group.to_string()];
+        for key in ini.group(&[group]).into_iter().flat_map(|g| g.keys()) {
+            if let Some(file) = kwinrc.non_user_source(&path, key) {
+                provenance.insert(format!("{group}/{key}"), file.clone());
+            }
         }
     }
 
-    Ok(ini)
-}
+    // kwinrulesrc
+    let rules = kconfig::load_cascade("kwinrulesrc", kwinrulesrc_path, config_dirs, diags)?;
+    let window_rules = parse_window_rules(&rules.effective);
+    summary.window_rules_count = window_rules.len();
 
-pub fn default_kwinrc() -> String {
-    let home = std::env::var("HOME").unwrap_or_else(|_| ".".into());
-    format!("{home}/.config/kwinrc")
-}
+    // Sort for stable UI output
+    for list in [
+        &mut summary.enabled_effects,
+        &mut summary.enabled_scripts,
+        &mut summary.disabled_effects,
+        &mut summary.disabled_scripts,
+        &mut summary.unknown_plugins,
+    ] {
+        list.sort();
+    }
 
-pub fn default_kwinrulesrc() -> String {
-    let home = std::env::var("HOME").unwrap_or_else(|_| ".".into());
-    format!("{home}/.config/kwinrulesrc")
+    Ok(Some(KWinScan {
+        kwinrc: kwinrc_path.to_string(),
+        kwinrulesrc: kwinrulesrc_path.to_string(),
+        provenance,
+        summary,
+        plugins,
+        window_rules,
+    }))
 }
 
-/// Tier-1 scan:
-/// - enabled effects/scripts from [Plugins] `*Enabled=true`
-/// - task switcher from [TabBox], [TabBoxAlternative]
-/// - window rules count from kwinrulesrc numeric sections [1], [2], ...
-pub fn load_kwin_info(kwinrc_path: &str, kwinrulesrc_path: &str) -> Result<KWinScan> {
-    let mut summary = KWinSummary::default();
+/// Metadata of an installed effect or script package.
+struct Package {
+    kind: KWinPluginKind,
+    name: Option<String>,
+    enabled_by_default: Option<bool>,
+    /// `None` for effects compiled into KWin
+    metadata: Option<PathBuf>,
+}
 
-    // kwinrc
-    if std::path::Path::new(kwinrc_path).exists() {
-        let ini = parse_ini(kwinrc_path)?;
-
-        if let Some(plugins) = ini.get_section("Plugins") {
-            for (k, v) in plugins {
-                // KDE stores as e.g. "blurEnabled=true" "kwin4_effect_translucencyEnabled=true"
-                if k.ends_with("Enabled") && v.eq_ignore_ascii_case("true") {
-                    let name = k.trim_end_matches("Enabled").to_string();
-                    // Very rough split: effects often start with "kwin4_effect_" but scripts can too.
-                    // We'll treat everything in Plugins as "enabled module" and later bucket by prefix.
-                    if name.contains("effect") || name.contains("kwin4_effect") {
-                        summary.enabled_effects.push(name);
-                    } else {
-                        summary.enabled_scripts.push(name);
-                    }
+/// Every `kwin/effects/*/metadata.json` and `kwin/scripts/*/metadata.json`
+/// under `data_dirs`, by KPlugin id, plus the `kwin/builtin-effects/*.json`
+/// KWin installs for its compiled-in effects. Earlier dirs win, like KPackage.
+fn kwin_packages(data_dirs: &[PathBuf]) -> BTreeMap<String, Package> {
+    let mut out = BTreeMap::new();
+    for dir in data_dirs {
+        if let Ok(entries) = fs::read_dir(dir.join("kwin/builtin-effects")) {
+            for entry in entries.filter_map(|e| e.ok()) {
+                let path = entry.path();
+                if path.extension().and_then(|e| e.to_str()) != Some("json") {
+                    continue;
+                }
+                if let Some((id, package)) = read_package(&path, KWinPluginKind::Effect) {
+                    let package = Package {
+                        metadata: None,
+                        ..package
+                    };
+                    out.entry(id).or_insert(package);
                 }
             }
         }
+        for (sub, kind) in [
+            ("kwin/effects", KWinPluginKind::Effect),
+            ("kwin/scripts", KWinPluginKind::Script),
+        ] {
+            let Ok(entries) = fs::read_dir(dir.join(sub)) else {
+                continue;
+            };
+            for entry in entries.filter_map(|e| e.ok()) {
+                let metadata = entry.path().join("metadata.json");
+                let Some((id, package)) = read_package(&metadata, kind) else {
+                    continue;
+                };
+                out.entry(id).or_insert(package);
+            }
+        }
+    }
+    out
+}
+
+/// Reads a package's metadata.json, or a built-in effect's `<id>.json`; the
+/// id falls back to the directory or file name.
+fn read_package(metadata: &Path, kind: KWinPluginKind) -> Option<(String, Package)> {
+    let text = fs::read_to_string(metadata).ok()?;
+    let json: serde_json::Value = serde_json::from_str(&text).ok()?;
+    let kplugin = &json["KPlugin"];
+    let id = match kplugin["Id"].as_str() {
+        Some(id) => id.to_string(),
+        None => {
+            let stem = metadata.file_stem()?;
+            let name = if stem == "metadata" {
+                metadata.parent()?.file_name()?
+            } else {
+                stem
+            };
+            name.to_string_lossy().into_owned()
+        }
+    };
+    // A package may declare its type; a script dropped into effects/ is still a script.
+    let kind = match json["KPackageStructure"].as_str() {
+        Some("KWin/Effect") => KWinPluginKind::Effect,
+        Some("KWin/Script") => KWinPluginKind::Script,
+        _ => kind,
+    };
+    Some((
+        id,
+        Package {
+            kind,
+            name: kplugin["Name"].as_str().map(str::to_string),
+            enabled_by_default: kplugin["EnabledByDefault"].as_bool(),
+            metadata: Some(metadata.to_path_buf()),
+        },
+    ))
+}
+
+/// Looks `id` up in the installed packages, then in KWin's built-in effects.
+fn resolve_plugin(id: &str, enabled: bool, packages: &BTreeMap<String, Package>) -> KWinPlugin {
+    let mut plugin = KWinPlugin {
+        id: id.to_string(),
+        kind: None,
+        name: None,
+        enabled,
+        enabled_by_default: None,
+        metadata: None,
+    };
+    if let Some(p) = packages.get(id) {
+        plugin.kind = Some(p.kind);
+        plugin.name = p.name.clone();
+        plugin.enabled_by_default = p.enabled_by_default;
+        plugin.metadata = p.metadata.as_ref().map(|m| m.display().to_string());
+    } else if let Some((_, name, default)) = BUILTIN_EFFECTS.iter().find(|b| b.0 == id) {
+        plugin.kind = Some(KWinPluginKind::Effect);
+        plugin.name = Some(name.to_string());
+        plugin.enabled_by_default = Some(*default);
+    }
+    plugin
+}
 
-        if let Some(tabbox) = ini.get_section("TabBox") {
-            summary.task_switcher = tabbox.clone();
+/// `[Desktops]`: `Number` desktops with `Id_<n>` and `Name_<n>`, 1-based.
+/// Without `Number` (or with a bad one) every numbered entry counts.
+fn parse_desktops(kv: &BTreeMap<String, String>) -> VirtualDesktops {
+    let count = kv
+        .get("Number")
+        .and_then(|n| n.trim().parse::<usize>().ok())
+        .unwrap_or_else(|| {
+            kv.keys()
+                .filter_map(|k| k.strip_prefix("Id_").or_else(|| k.strip_prefix("Name_")))
+                .filter_map(|n| n.parse().ok())
+                .max()
+                .unwrap_or(1)
+        });
+    VirtualDesktops {
+        rows: kv.get("Rows").and_then(|r| r.trim().parse().ok()),
+        desktops: (1..=count)
+            .map(|n| VirtualDesktop {
+                id: kv.get(&format!("Id_{n}")).cloned(),
+                name: kv.get(&format!("Name_{n}")).cloned(),
+            })
+            .collect(),
+    }
+}
+
+/// Group names of the rules, in the order KWin applies them: `[General]
+/// rules=` on Plasma 5.20 and later, `[1]` .. `[count]` before that.
+fn rule_ids(ini: &IniTree) -> Vec<String> {
+    let general = ini.group(&["General"]);
+    if let Some(list) = general.and_then(|g| g.get("rules")) {
+        return split_list_raw(list)
+            .into_iter()
+            .filter(|id| !id.is_empty())
+            .collect();
+    }
+    let count = general
+        .and_then(|g| g.get("count"))
+        .and_then(|c| c.trim().parse::<usize>().ok());
+    match count {
+        Some(n) => (1..=n).map(|i| i.to_string()).collect(),
+        // No index at all: every numeric group, in number order.
+        None => {
+            let mut ids: Vec<usize> = ini
+                .iter_sections()
+                .filter(|(path, _)| path.len() == 1)
+                .filter_map(|(path, _)| path[0].parse().ok())
+                .collect();
+            ids.sort_unstable();
+            ids.into_iter().map(|i| i.to_string()).collect()
         }
-        if let Some(tabbox_alt) = ini.get_section("TabBoxAlternative") {
-            summary.task_switcher_alternative = tabbox_alt.clone();
+    }
+}
+
+/// Reads every rule listed in kwinrulesrc. Listed ids without a group are
+/// skipped; KWin would load them as empty rules.
+pub(crate) fn parse_window_rules(ini: &IniTree) -> Vec<WindowRule> {
+    rule_ids(ini)
+        .into_iter()
+        .filter_map(|id| {
+            let kv = ini.section(std::slice::from_ref(&id))?;
+            Some(parse_window_rule(id, kv))
+        })
+        .collect()
+}
+
+fn parse_window_rule(id: String, kv: &BTreeMap<String, String>) -> WindowRule {
+    let window_match = |key: &str, match_key: &str| {
+        let value = kv.get(key);
+        let kind = kv.get(match_key);
+        if value.is_none() && kind.is_none() {
+            return None;
+        }
+        Some(WindowMatch {
+            value: value.cloned().unwrap_or_default(),
+            kind: kind.and_then(|k| MatchKind::decode(k)),
+        })
+    };
+    let mut rule = WindowRule {
+        description: kv.get("Description").cloned().unwrap_or_default(),
+        wmclass: window_match(MATCH_KEYS[0].0, MATCH_KEYS[0].1),
+        wmclass_complete: kv
+            .get("wmclasscomplete")
+            .is_some_and(|v| v.trim().eq_ignore_ascii_case("true")),
+        title: window_match(MATCH_KEYS[1].0, MATCH_KEYS[1].1),
+        role: window_match(MATCH_KEYS[2].0, MATCH_KEYS[2].1),
+        machine: window_match(MATCH_KEYS[3].0, MATCH_KEYS[3].1),
+        types: kv.get("types").and_then(|t| t.trim().parse().ok()),
+        id,
+        ..WindowRule::default()
+    };
+
+    let matching = |k: &str| {
+        matches!(k, "Description" | "wmclasscomplete" | "types")
+            || MATCH_KEYS.iter().any(|(v, m)| k == *v || k == *m)
+    };
+    for (key, value) in kv.iter().filter(|(k, _)| !matching(k)) {
+        // `aboverule=2` is the policy of `above=true`
+        let (name, is_policy) = match key.strip_suffix("rule") {
+            Some(name) if !name.is_empty() => (name, true),
+            _ => (key.as_str(), false),
+        };
+        let prop = rule
+            .properties
+            .entry(name.to_string())
+            .or_insert_with(|| RuleProperty {
+                value: String::new(),
+                policy: None,
+            });
+        if is_policy {
+            prop.policy = RulePolicy::decode(value);
+        } else {
+            prop.value = value.clone();
         }
     }
+    rule
+}
 
-    // kwinrulesrc: count numeric groups like [1], [2], ...
-    if std::path::Path::new(kwinrulesrc_path).exists() {
-        let raw = fs::read_to_string(kwinrulesrc_path)
-            .with_context(|| format!("reading {kwinrulesrc_path}"))?;
-        let re = Regex::new(r"^\s*\[(\d+)\]\s*$")?;
-        let mut max_id = 0usize;
-        let mut seen = std::collections::BTreeSet::new();
-        for line in raw.lines() {
-            if let Some(c) = re.captures(line) {
-                if let Ok(n) = c.get(1).unwrap().as_str().parse::<usize>() {
-                    seen.insert(n);
-                    if n > max_id {
-                        max_id = n;
-                    }
-                }
-            }
+/// The kwinrulesrc entries of `rule`, as KWin writes them.
+fn rule_entries(rule: &WindowRule) -> BTreeMap<String, String> {
+    let mut kv = BTreeMap::new();
+    if !rule.description.is_empty() {
+        kv.insert("Description".to_string(), rule.description.clone());
+    }
+    let matches = [&rule.wmclass, &rule.title, &rule.role, &rule.machine];
+    for ((key, match_key), m) in MATCH_KEYS.iter().zip(matches) {
+        let Some(m) = m else { continue };
+        kv.insert(key.to_string(), m.value.clone());
+        if let Some(kind) = m.kind {
+            kv.insert(match_key.to_string(), kind.number().to_string());
         }
-        // Some files might skip numbers; count distinct numeric groups.
-        summary.window_rules_count = seen.len();
     }
+    if rule.wmclass_complete {
+        kv.insert("wmclasscomplete".to_string(), "true".to_string());
+    }
+    if let Some(types) = rule.types {
+        kv.insert("types".to_string(), types.to_string());
+    }
+    for (name, prop) in &rule.properties {
+        kv.insert(name.clone(), prop.value.clone());
+        if let Some(policy) = prop.policy {
+            kv.insert(format!("{name}rule"), policy.number().to_string());
+        }
+    }
+    kv
+}
 
-    // Sort for stable UI output
-    summary.enabled_effects.sort();
-    summary.enabled_scripts.sort();
+/// Group name a rule gets in the fragment. Numbered groups would collide
+/// with the target machine's own `[1]`, `[2]`, ..., so they get a name
+/// derived from their content; importing the same rule twice replaces it.
+fn fragment_id(rule: &WindowRule, kv: &BTreeMap<String, String>) -> String {
+    if rule.id.parse::<u64>().is_err() {
+        return rule.id.clone();
+    }
+    // FNV-1a: stable across builds, unlike std's hasher.
+    let mut hash: u32 = 0x811c_9dc5;
+    for (k, v) in kv {
+        for b in k.bytes().chain([b'=']).chain(v.bytes()).chain([b'\n']) {
+            hash ^= u32::from(b);
+            hash = hash.wrapping_mul(0x0100_0193);
+        }
+    }
+    format!("imported-{hash:08x}")
+}
 
-    Ok(KWinScan {
-        kwinrc: kwinrc_path.to_string(),
-        kwinrulesrc: kwinrulesrc_path.to_string(),
-        summary,
-    })
+/// A kwinrulesrc holding just `rules` and their `[General]` index, for
+/// restore-kwin-rules.sh to merge into the target machine's rules.
+pub fn kwinrules_fragment(rules: &[WindowRule]) -> String {
+    let mut out = String::new();
+    let mut ids = Vec::new();
+    for rule in rules {
+        let kv = rule_entries(rule);
+        let id = fragment_id(rule, &kv);
+        out.push_str(&format!("[{}]\n", kconfig::escape(&id, Escape::Group)));
+        for (k, v) in &kv {
+            out.push_str(&format!(
+                "{}={}\n",
+                kconfig::escape(k, Escape::Key),
+                kconfig::escape(v, Escape::Value)
+            ));
+        }
+        out.push('\n');
+        ids.push(id.replace(',', "\\,"));
+    }
+    out.push_str(&format!(
+        "[General]\ncount={}\nrules={}\n",
+        ids.len(),
+        ids.join(",")
+    ));
+    out
 }
@@ -0,0 +1,55 @@
+//! Read KDE Plasma 6 desktop layouts (panels, widgets, desktops, KWin
+//! settings) into a serializable [`Layout`] and turn them into portable
+//! bundles that restore the layout on another machine.
+//!
+//! ```no_run
+//! # fn main() -> anyhow::Result<()> {
+//! use kdesktop_copycat::export::Exporter;
+//! use kdesktop_copycat::input::{self, ConfigFiles};
+//! use kdesktop_copycat::paths::Paths;
+//!
+//! // This user's layout, merged over the system defaults
+//! let paths = Paths::from_env();
+//! let layout = input::load_config(&paths, ConfigFiles::default())?;
+//! for c in layout.containments.iter().filter(|c| c.is_panel) {
+//!     println!("panel {} has {} widgets", c.id, c.applets.len());
+//! }
+//!
+//! // Bundle with layout.json, restore scripts and a snapshot
+//! let bundle = Exporter::new("/tmp/bundles").paths(paths).export(&layout)?;
+//! println!("wrote {}", bundle.dir.display());
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! layout.json is the serialized [`Layout`]; see [`schema`] for its JSON
+//! Schema and for reading files written by older versions.
+
+/// Plasma activities from kactivitymanagerdrc.
+pub mod activities;
+/// Parse diagnostics with file/line/column and their rustc-style rendering.
+pub mod diagnostics;
+/// Writing bundles.
+pub mod export;
+/// Where layouts come from: live config files, layout.json or bundles.
+pub mod input;
+mod kconfig;
+/// KWin settings from kwinrc and kwinrulesrc.
+pub mod kwin;
+/// The layout model serialized as layout.json.
+pub mod model;
+/// Reading and writing the Plasma desktop layout (appletsrc).
+pub mod parser;
+/// XDG config and data directories.
+pub mod paths;
+/// Panel geometry and screen connectors from plasmashellrc.
+pub mod plasmashell;
+/// layout.json versioning, JSON Schema and migrations.
+pub mod schema;
+/// Monitor setup from kscreen.
+pub mod screens;
+/// Restore scripts shipped in bundles.
+pub mod scripts;
+
+pub use export::{Bundle, Exporter};
+pub use model::{Layout, SCHEMA_VERSION};
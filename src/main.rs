@@ -1,16 +1,12 @@
-mod export;
-mod kwin;
-mod model;
-mod parser;
-mod scripts;
 mod tui;
 
-use anyhow::Result;
-use clap::{Parser, Subcommand};
+use anyhow::{Context, Result};
+use clap::{Args, Parser, Subcommand};
 use std::path::PathBuf;
 
-use crate::kwin::{default_kwinrc, default_kwinrulesrc, load_kwin_info};
-use crate::parser::parse_appletsrc;
+use kdesktop_copycat::input::{self, ConfigFiles, Loaded};
+use kdesktop_copycat::paths::Paths;
+use kdesktop_copycat::{diagnostics, schema, Exporter};
 
 #[derive(Parser)]
 #[command(
@@ -19,6 +15,11 @@ use crate::parser::parse_appletsrc;
     about = "Turn your KDE Plasma 6 panels, widgets, and desktops into a portable bundle."
 )]
 struct Cli {
+    /// Treat this directory as the home directory for every default path
+    /// (e.g. a mounted backup); ignores XDG_* variables and /etc/xdg
+    #[arg(long, global = true)]
+    root: Option<PathBuf>,
+
     #[command(subcommand)]
     cmd: Command,
 }
@@ -27,32 +28,17 @@ struct Cli {
 enum Command {
     /// Parse plasma-org.kde.plasma.desktop-appletsrc and print JSON to stdout
     Scan {
-        /// Optional appletsrc path (defaults to ~/.config/plasma-org.kde.plasma.desktop-appletsrc)
-        #[arg(long)]
-        file: Option<String>,
-
-        /// Optional kwinrc path (defaults to ~/.config/kwinrc)
-        #[arg(long)]
-        kwinrc: Option<String>,
-
-        /// Optional kwinrulesrc path (defaults to ~/.config/kwinrulesrc)
-        #[arg(long)]
-        kwinrules: Option<String>,
+        #[command(flatten)]
+        input: InputArgs,
     },
 
     /// Open a TUI viewer for the parsed layout
     Tui {
-        #[arg(long)]
-        file: Option<String>,
-
-        #[arg(long)]
-        kwinrc: Option<String>,
-
-        #[arg(long)]
-        kwinrules: Option<String>,
+        #[command(flatten)]
+        input: InputArgs,
     },
 
-    /// Export a bundle (layout.json + scripts + optional snapshot + optional plasmoids)
+    /// Export a bundle (layout.json + scripts + optional snapshot, plasmoids and KWin packages)
     Export {
         #[arg(long)]
         out: PathBuf,
@@ -63,88 +49,149 @@ enum Command {
         #[arg(long, default_value_t = true)]
         bundle_plasmoids: bool,
 
-        #[arg(long)]
-        file: Option<String>,
+        /// Include user-installed KWin scripts, effects and decorations that kwinrc uses
+        #[arg(long, default_value_t = true)]
+        bundle_kwin_packages: bool,
 
-        #[arg(long)]
-        kwinrc: Option<String>,
+        /// Restore what was on output FROM onto output TO, e.g. DP-1=HDMI-A-1 (repeatable)
+        #[arg(long = "map-screen", value_name = "FROM=TO", value_parser = parse_screen_map)]
+        map_screen: Vec<(String, String)>,
 
-        #[arg(long)]
-        kwinrules: Option<String>,
+        #[command(flatten)]
+        input: InputArgs,
+    },
+
+    /// Print the JSON Schema of layout.json
+    Schema,
+
+    /// Upgrade a layout.json written by an older version and print it
+    Migrate {
+        /// layout.json to read
+        file: PathBuf,
     },
 }
 
-fn default_appletsrc() -> String {
-    let home = std::env::var("HOME").unwrap_or_else(|_| ".".into());
-    format!("{home}/.config/plasma-org.kde.plasma.desktop-appletsrc")
+/// Where the layout comes from; shared by every command that reads one.
+#[derive(Args)]
+struct InputArgs {
+    /// Read an existing layout instead of this machine's config: an appletsrc,
+    /// a layout.json, a bundle directory or a bundle archive (.tar, .tar.gz, ...)
+    #[arg(long, value_name = "PATH", conflicts_with_all = ["file", "kwinrc", "kwinrules", "plasmashellrc"])]
+    from: Option<PathBuf>,
+
+    /// Optional appletsrc path (defaults to $XDG_CONFIG_HOME/plasma-org.kde.plasma.desktop-appletsrc)
+    #[arg(long)]
+    file: Option<String>,
+
+    /// Optional kwinrc path (defaults to $XDG_CONFIG_HOME/kwinrc)
+    #[arg(long)]
+    kwinrc: Option<String>,
+
+    /// Optional kwinrulesrc path (defaults to $XDG_CONFIG_HOME/kwinrulesrc)
+    #[arg(long)]
+    kwinrules: Option<String>,
+
+    /// Optional plasmashellrc path (defaults to $XDG_CONFIG_HOME/plasmashellrc)
+    #[arg(long)]
+    plasmashellrc: Option<String>,
+
+    /// Fail if the parser reports any diagnostics
+    #[arg(long)]
+    strict: bool,
+}
+
+/// `FROM=TO` connector names for `--map-screen`.
+fn parse_screen_map(s: &str) -> Result<(String, String), String> {
+    match s.split_once('=') {
+        Some((from, to)) if !from.trim().is_empty() && !to.trim().is_empty() => {
+            Ok((from.trim().to_string(), to.trim().to_string()))
+        }
+        _ => Err(format!("expected FROM=TO (e.g. DP-1=HDMI-A-1), got `{s}`")),
+    }
+}
+
+/// Loads the layout for every command: `--from` as it is, otherwise this
+/// machine's config files.
+fn load_layout(paths: &Paths, input: InputArgs) -> Result<Loaded> {
+    let loaded = match &input.from {
+        Some(from) => input::load(from)?,
+        None => input::load_config(
+            paths,
+            ConfigFiles {
+                appletsrc: input.file,
+                kwinrc: input.kwinrc,
+                kwinrulesrc: input.kwinrules,
+                plasmashellrc: input.plasmashellrc,
+            },
+        )?
+        .into(),
+    };
+    diagnostics::report(&loaded.layout.diagnostics, input.strict)?;
+    Ok(loaded)
 }
 
 fn main() -> Result<()> {
     let cli = Cli::parse();
+    let paths = match &cli.root {
+        Some(root) => Paths::with_root(root),
+        None => Paths::from_env(),
+    };
 
     match cli.cmd {
-        Command::Scan {
-            file,
-            kwinrc,
-            kwinrules,
-        } => {
-            let applets = file.unwrap_or_else(default_appletsrc);
-            let mut layout = parse_appletsrc(&applets)?;
-
-            let kwinrc_path = kwinrc.unwrap_or_else(default_kwinrc);
-            let kwinrules_path = kwinrules.unwrap_or_else(default_kwinrulesrc);
+        Command::Scan { input } => {
+            let loaded = load_layout(&paths, input)?;
 
-            if std::path::Path::new(&kwinrc_path).exists() {
-                layout.kwin = Some(load_kwin_info(&kwinrc_path, &kwinrules_path)?);
-            }
-
-            println!("{}", serde_json::to_string_pretty(&layout)?);
+            println!("{}", serde_json::to_string_pretty(&loaded.layout)?);
         }
 
-        Command::Tui {
-            file,
-            kwinrc,
-            kwinrules,
-        } => {
-            let applets = file.unwrap_or_else(default_appletsrc);
-            let mut layout = parse_appletsrc(&applets)?;
-
-            let kwinrc_path = kwinrc.unwrap_or_else(default_kwinrc);
-            let kwinrules_path = kwinrules.unwrap_or_else(default_kwinrulesrc);
+        Command::Tui { input } => {
+            let loaded = load_layout(&paths, input)?;
 
-            if std::path::Path::new(&kwinrc_path).exists() {
-                layout.kwin = Some(load_kwin_info(&kwinrc_path, &kwinrules_path)?);
-            }
-
-            tui::run(layout)?;
+            tui::run(loaded.layout)?;
         }
 
         Command::Export {
             out,
             snapshot,
             bundle_plasmoids,
-            file,
-            kwinrc,
-            kwinrules,
+            bundle_kwin_packages,
+            map_screen,
+            input,
         } => {
-            let applets = file.unwrap_or_else(default_appletsrc);
-            let mut layout = parse_appletsrc(&applets)?;
-
-            let kwinrc_path = kwinrc.unwrap_or_else(default_kwinrc);
-            let kwinrules_path = kwinrules.unwrap_or_else(default_kwinrulesrc);
+            let loaded = load_layout(&paths, input)?;
+
+            let mut exporter = Exporter::new(out)
+                .paths(paths)
+                .snapshot(snapshot)
+                .bundle_plasmoids(bundle_plasmoids)
+                .bundle_kwin_packages(bundle_kwin_packages);
+            for (from, to) in map_screen {
+                exporter = exporter.map_screen(from, to);
+            }
+            if let Some(dir) = &loaded.bundle_dir {
+                exporter = exporter.source_bundle(dir);
+            }
 
-            if std::path::Path::new(&kwinrc_path).exists() {
-                layout.kwin = Some(load_kwin_info(&kwinrc_path, &kwinrules_path)?);
+            let bundle = exporter.export(&loaded.layout)?;
+            for w in &bundle.warnings {
+                eprintln!("warning: {w}");
             }
+            eprintln!("Exported bundle: {}", bundle.dir.display());
+            for entry in &bundle.contents {
+                eprintln!(" - {entry}");
+            }
+        }
 
-            // IMPORTANT: your ExportOpts has `snapshot` (per compiler error), not `include_snapshot`
-            let opts = export::ExportOpts {
-                out,
-                snapshot,
-                bundle_plasmoids,
-            };
+        Command::Schema => {
+            println!("{}", serde_json::to_string_pretty(&schema::json_schema())?);
+        }
 
-            export::export_bundle(&layout, opts)?;
+        Command::Migrate { file } => {
+            let text = std::fs::read_to_string(&file)
+                .with_context(|| format!("reading {}", file.display()))?;
+            let layout = schema::load_layout_json(&text, &file.display().to_string())?;
+
+            println!("{}", serde_json::to_string_pretty(&layout)?);
         }
     }
 
@@ -1,17 +1,194 @@
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+use crate::diagnostics::Diagnostic;
+use crate::kconfig::IniTree;
+
+/// Version of the layout.json format written by this build; bumped on any
+/// change older readers would misread (see `schema::load_layout_json`).
+pub const SCHEMA_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct Layout {
+    /// Format version of this file (`SCHEMA_VERSION` when written);
+    /// missing in files from before versioning, which count as 0
+    #[serde(default)]
+    pub schema_version: u32,
+
     pub source_file: String,
+
+    /// Every file merged into this layout, least specific first (system
+    /// defaults from the XDG config dirs, then `source_file`)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub sources: Vec<String>,
     pub containments: Vec<Containment>,
 
+    /// Plasma activities from kactivitymanagerdrc, in the order they were listed
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub activities: Vec<Activity>,
+
+    /// Screen ids, output connectors and monitor setup
+    #[serde(default, skip_serializing_if = "Screens::is_empty")]
+    pub screens: Screens,
+
+    /// Global `[ActionPlugins]` (mouse actions per containment type: `0`
+    /// desktops, `1` panels), keyed by group path below it joined with `/`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub action_plugins: BTreeMap<String, BTreeMap<String, String>>,
+
     /// Optional Tier-1 KWin scan (kwinrc / kwinrulesrc summaries)
     #[serde(skip_serializing_if = "Option::is_none")]
     pub kwin: Option<KWinScan>,
+
+    /// Anomalies the parsers recovered from, in file, line and column order
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub diagnostics: Vec<Diagnostic>,
+
+    /// Lossless syntax tree of `source_file`, used to write the file back
+    /// with minimal changes. Not part of layout.json.
+    #[serde(skip)]
+    pub(crate) syntax: Option<IniTree>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+impl Layout {
+    /// Containment with `id`.
+    pub fn containment(&self, id: u32) -> Option<&Containment> {
+        self.containments.iter().find(|c| c.id == id)
+    }
+
+    /// Activity with `id`.
+    pub fn activity(&self, id: &str) -> Option<&Activity> {
+        self.activities.iter().find(|a| a.id == id)
+    }
+
+    /// Containments in display order: each top-level containment followed by
+    /// the containments nested in its applets (e.g. the system tray).
+    /// Containments outside any activity (panels) come first, then desktops
+    /// grouped by activity.
+    pub fn containment_tree(&self) -> Vec<(usize, &Containment)> {
+        let mut top: Vec<&Containment> = self
+            .containments
+            .iter()
+            .filter(|c| c.parent_applet.is_none())
+            .collect();
+        top.sort_by_key(|c| match c.activity() {
+            None => 0,
+            Some(id) => self
+                .activities
+                .iter()
+                .position(|a| a.id == id)
+                .map_or(usize::MAX, |i| i + 1),
+        });
+        let mut out = Vec::new();
+        for c in top {
+            self.push_subtree(c, 0, &mut out);
+        }
+        out
+    }
+
+    fn push_subtree<'a>(
+        &'a self,
+        c: &'a Containment,
+        depth: usize,
+        out: &mut Vec<(usize, &'a Containment)>,
+    ) {
+        out.push((depth, c));
+        for a in c.applets_in_order() {
+            if let Some(child) = a.child_containment.and_then(|id| self.containment(id)) {
+                // A malformed file could point a tray back at an ancestor.
+                if out.iter().all(|(_, seen)| seen.id != child.id) {
+                    self.push_subtree(child, depth + 1, out);
+                }
+            }
+        }
+    }
+}
+
+/// A Plasma activity; desktops belong to one through their `activityId`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct Activity {
+    pub id: String,
+    pub name: String,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub icon: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+
+    /// The activity that was active when the layout was captured
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub current: bool,
+
+    /// Ids of the desktop containments in this activity
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub containments: Vec<u32>,
+}
+
+/// How Plasma screen ids (`lastScreen`) map to physical outputs.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct Screens {
+    /// plasmashellrc `[ScreenConnectors]`: screen id → connector (`DP-1`)
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub connectors: BTreeMap<u32, String>,
+
+    /// Outputs of the newest kscreen config under `$XDG_DATA_HOME/kscreen`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub outputs: Vec<Output>,
+
+    /// The kscreen config `outputs` came from
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kscreen_config: Option<String>,
+
+    /// appletsrc `[ScreenMapping]`: desktop icons placed on a specific screen
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub icon_mapping: Vec<IconScreen>,
+}
+
+impl Screens {
+    pub fn is_empty(&self) -> bool {
+        self == &Screens::default()
+    }
+
+    pub fn connector(&self, screen: u32) -> Option<&str> {
+        self.connectors.get(&screen).map(String::as_str)
+    }
+}
+
+/// One monitor as kscreen saved it.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct Output {
+    /// Connector name, e.g. `DP-1`
+    pub connector: String,
+
+    /// Monitor model, when kscreen knows it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+
+    pub enabled: bool,
+    pub primary: bool,
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub refresh: Option<f64>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scale: Option<f64>,
+}
+
+/// A `screenMapping` entry: the file at `url` sits on `screen` in `activity`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct IconScreen {
+    pub url: String,
+    pub screen: u32,
+    pub activity: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct Containment {
     pub id: u32,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -21,14 +198,109 @@ pub struct Containment {
     /// Raw key/value pairs from the containment section
     pub meta: BTreeMap<String, String>,
 
+    /// Decoded `location`
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        deserialize_with = "lenient"
+    )]
+    pub location: Option<Location>,
+
+    /// Decoded `formfactor`
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        deserialize_with = "lenient"
+    )]
+    pub form_factor: Option<FormFactor>,
+
+    /// Decoded `immutability`
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        deserialize_with = "lenient"
+    )]
+    pub immutability: Option<Immutability>,
+
+    /// Localized keys from the containment section
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub localized: Localized,
+
+    /// KConfig markers on the containment section
+    #[serde(default, skip_serializing_if = "Markers::is_empty")]
+    pub markers: Markers,
+
+    /// Keys whose value comes from a system file rather than `source_file`, with that file
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub provenance: BTreeMap<String, String>,
+
     pub applets: Vec<Applet>,
 
     /// Panel widget order (AppletOrder) if present
     #[serde(skip_serializing_if = "Option::is_none")]
     pub applet_order: Option<Vec<u32>>,
+
+    /// Applet this containment belongs to (the system tray applet for a
+    /// tray containment); `None` for desktops and panels
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent_applet: Option<u32>,
+
+    /// Item lists from `[General]` of a system tray containment
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub systray: Option<SystrayItems>,
+
+    /// Size and visibility of a panel, from plasmashellrc
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub geometry: Option<PanelGeometry>,
+
+    /// Wallpaper of a desktop containment
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub wallpaper: Option<Wallpaper>,
+
+    /// Groups below the containment section other than `Applets` and
+    /// `Wallpaper` (`General`, `ConfigDialog`, ...), keyed by path joined with `/`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub config: BTreeMap<String, BTreeMap<String, String>>,
+
+    /// Localized keys per configuration group (only groups that have any)
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub config_localized: BTreeMap<String, Localized>,
+
+    /// KConfig markers per configuration group (only groups that have any)
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub config_markers: BTreeMap<String, Markers>,
 }
 
 impl Containment {
+    /// `activityId`; `None` for containments shown in every activity (panels).
+    pub fn activity(&self) -> Option<&str> {
+        self.meta
+            .get("activityId")
+            .map(|v| v.trim())
+            .filter(|v| !v.is_empty())
+    }
+
+    /// `lastScreen`: the screen the containment was last shown on.
+    pub fn screen(&self) -> Option<u32> {
+        self.meta
+            .get("lastScreen")
+            .and_then(|v| v.trim().parse().ok())
+    }
+
+    /// Locked with `[$i]` (directly, by a parent group, or for the whole file).
+    pub fn is_locked(&self) -> bool {
+        self.markers.immutable
+    }
+
+    /// True if `key` in configuration `group` must not be written back.
+    pub fn is_config_key_locked(&self, group: &str, key: &str) -> bool {
+        self.is_locked()
+            || self
+                .config_markers
+                .get(group)
+                .is_some_and(|m| m.is_key_locked(key))
+    }
+
     /// Returns applets sorted using AppletOrder if present, otherwise by id.
     pub fn applets_in_order(&self) -> Vec<&Applet> {
         if let Some(order) = &self.applet_order {
@@ -53,7 +325,7 @@ impl Containment {
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct Applet {
     pub id: u32,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -62,11 +334,466 @@ pub struct Applet {
     /// Raw key/value pairs from the applet section
     pub meta: BTreeMap<String, String>,
 
-    /// Configuration groups under Applets/<id>/Configuration/...
+    /// Localized keys from the applet section
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub localized: Localized,
+
+    /// Configuration groups under `Applets/<id>/Configuration/...`
+    pub config: BTreeMap<String, BTreeMap<String, String>>,
+
+    /// Localized keys per configuration group (only groups that have any)
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub config_localized: BTreeMap<String, Localized>,
+
+    /// KConfig markers on the applet section
+    #[serde(default, skip_serializing_if = "Markers::is_empty")]
+    pub markers: Markers,
+
+    /// KConfig markers per configuration group (only groups that have any)
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub config_markers: BTreeMap<String, Markers>,
+
+    /// Like `Containment::provenance`; configuration keys are written `group/key`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub provenance: BTreeMap<String, String>,
+
+    /// Subgroups of the applet section other than `Configuration`
+    /// (`Shortcuts`, ...), keyed by path joined with `/`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub groups: BTreeMap<String, BTreeMap<String, String>>,
+
+    /// Localized keys per subgroup (only groups that have any)
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub groups_localized: BTreeMap<String, Localized>,
+
+    /// Global shortcut that activates the widget (`[Shortcuts] global`,
+    /// e.g. `Alt+F1` for the launcher)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shortcut: Option<String>,
+
+    /// Containment holding this applet's own widgets (`SystrayContainmentId`
+    /// of a system tray)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub child_containment: Option<u32>,
+}
+
+impl Applet {
+    /// Locked with `[$i]` (directly, by a parent group, or for the whole file).
+    pub fn is_locked(&self) -> bool {
+        self.markers.immutable
+    }
+
+    /// True if `key` in configuration `group` must not be written back.
+    pub fn is_config_key_locked(&self, group: &str, key: &str) -> bool {
+        self.is_locked()
+            || self
+                .config_markers
+                .get(group)
+                .is_some_and(|m| m.is_key_locked(key))
+    }
+}
+
+/// `wallpaperplugin` and its settings from `[Wallpaper][<plugin>]`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct Wallpaper {
+    /// e.g. `org.kde.image`, `org.kde.slideshow`, `org.kde.color`
+    pub plugin: String,
+
+    /// Groups under `[Wallpaper][<plugin>]`, e.g. `General`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
     pub config: BTreeMap<String, BTreeMap<String, String>>,
+
+    /// KConfig markers per group of `config` (only groups that have any)
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub config_markers: BTreeMap<String, Markers>,
+
+    /// Local files and directories the settings point at (images, slideshow
+    /// folders), as written in the config without `file://` and with
+    /// `[$e]` values expanded
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub files: Vec<String>,
+
+    /// In bundles: entry of `files` → name of its copy under the bundle's
+    /// `wallpapers/`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub bundled: BTreeMap<String, String>,
+}
+
+/// Which tray entries are enabled and how they are shown.
+///
+/// Entries are plugin ids (`org.kde.plasma.battery`) or StatusNotifierItem
+/// ids (`org.kde.konsole`).
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct SystrayItems {
+    /// Plasmoids enabled in the tray (`extraItems`)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub extra_items: Vec<String>,
+
+    /// Plasmoids the tray has offered before (`knownItems`)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub known_items: Vec<String>,
+
+    /// Always shown (`shownItems`)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub shown_items: Vec<String>,
+
+    /// Always hidden in the popup (`hiddenItems`)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub hidden_items: Vec<String>,
+}
+
+/// Panel settings from `[PlasmaViews][Panel N]` in plasmashellrc.
+///
+/// Thickness, length and offset depend on the screen, so Plasma keeps them
+/// per screen size in subgroups like `[Horizontal1920]`; `[Defaults]` holds
+/// the values used for screens without one.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct PanelGeometry {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub alignment: Option<PanelAlignment>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hiding: Option<PanelHiding>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub floating: Option<bool>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub length_mode: Option<PanelLengthMode>,
+
+    /// `[Defaults]` and the view group itself
+    #[serde(default, skip_serializing_if = "PanelSize::is_empty")]
+    pub defaults: PanelSize,
+
+    /// Per screen size subgroup, e.g. `Horizontal1920` → sizes
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub screens: BTreeMap<String, PanelSize>,
+}
+
+impl PanelGeometry {
+    pub fn is_empty(&self) -> bool {
+        self == &PanelGeometry::default()
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct PanelSize {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub thickness: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub length: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_length: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_length: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub offset: Option<i32>,
+}
+
+impl PanelSize {
+    pub fn is_empty(&self) -> bool {
+        self == &PanelSize::default()
+    }
+}
+
+/// Where the panel sits along its edge (`alignment`, a Qt::Alignment value).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum PanelAlignment {
+    Left,
+    Center,
+    Right,
+}
+
+impl PanelAlignment {
+    /// Decodes Qt::AlignLeft (1), AlignRight (2) and AlignHCenter (4), with
+    /// or without vertical bits.
+    pub fn from_qt(v: u32) -> Option<Self> {
+        if v & 0x4 != 0 {
+            Some(PanelAlignment::Center)
+        } else if v & 0x2 != 0 {
+            Some(PanelAlignment::Right)
+        } else if v & 0x1 != 0 {
+            Some(PanelAlignment::Left)
+        } else {
+            None
+        }
+    }
+
+    /// Value for `panel.alignment` in Plasma scripting.
+    pub fn script_name(self) -> &'static str {
+        match self {
+            PanelAlignment::Left => "left",
+            PanelAlignment::Center => "center",
+            PanelAlignment::Right => "right",
+        }
+    }
+}
+
+/// `panelVisibility`: how the panel gets out of the way of windows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum PanelHiding {
+    None,
+    AutoHide,
+    DodgeWindows,
+    WindowsGoBelow,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+impl PanelHiding {
+    pub fn from_config(v: u32) -> Option<Self> {
+        match v {
+            0 => Some(PanelHiding::None),
+            1 => Some(PanelHiding::AutoHide),
+            2 => Some(PanelHiding::DodgeWindows),
+            3 => Some(PanelHiding::WindowsGoBelow),
+            _ => None,
+        }
+    }
+
+    /// Value for `panel.hiding` in Plasma scripting.
+    pub fn script_name(self) -> &'static str {
+        match self {
+            PanelHiding::None => "none",
+            PanelHiding::AutoHide => "autohide",
+            PanelHiding::DodgeWindows => "dodgewindows",
+            PanelHiding::WindowsGoBelow => "windowsgobelow",
+        }
+    }
+}
+
+/// `panelLengthMode`: fill the edge, fit the widgets, or a custom length.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum PanelLengthMode {
+    Fill,
+    Fit,
+    Custom,
+}
+
+impl PanelLengthMode {
+    pub fn from_config(v: u32) -> Option<Self> {
+        match v {
+            0 => Some(PanelLengthMode::Fill),
+            1 => Some(PanelLengthMode::Fit),
+            2 => Some(PanelLengthMode::Custom),
+            _ => None,
+        }
+    }
+
+    /// Value for `panel.lengthMode` in Plasma scripting.
+    pub fn script_name(self) -> &'static str {
+        match self {
+            PanelLengthMode::Fill => "fill",
+            PanelLengthMode::Fit => "fit",
+            PanelLengthMode::Custom => "custom",
+        }
+    }
+}
+
+/// Values stored as numbers in appletsrc that also have a readable name.
+pub trait ConfigEnum: Sized + Copy + PartialEq + 'static {
+    /// Every variant with its number and name.
+    const VARIANTS: &'static [(Self, u32, &'static str)];
+
+    /// The name this value serializes as.
+    fn name(self) -> &'static str {
+        Self::VARIANTS
+            .iter()
+            .find(|v| v.0 == self)
+            .map_or("", |v| v.2)
+    }
+
+    /// The number Plasma writes for this value.
+    fn number(self) -> u32 {
+        Self::VARIANTS
+            .iter()
+            .find(|v| v.0 == self)
+            .map_or(0, |v| v.1)
+    }
+
+    /// Accepts the number Plasma writes or the name (any case, with or
+    /// without `-`/`_`, e.g. `BottomEdge`, `bottom-edge`); `None` otherwise.
+    fn decode(raw: &str) -> Option<Self> {
+        let raw = raw.trim();
+        if let Ok(n) = raw.parse::<u32>() {
+            return Self::VARIANTS.iter().find(|v| v.1 == n).map(|v| v.0);
+        }
+        let norm = |s: &str| -> String {
+            s.chars()
+                .filter(|c| c.is_ascii_alphanumeric())
+                .map(|c| c.to_ascii_lowercase())
+                .collect()
+        };
+        let want = norm(raw);
+        Self::VARIANTS
+            .iter()
+            .find(|v| norm(v.2) == want || norm(v.2).trim_end_matches("edge") == want)
+            .map(|v| v.0)
+    }
+}
+
+/// Decodes a [`ConfigEnum`] from a number or a name; anything else
+/// (including values from newer Plasma versions) becomes `None`.
+fn lenient<'de, D, T>(d: D) -> Result<Option<T>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: ConfigEnum,
+{
+    let v = Option::<serde_json::Value>::deserialize(d)?;
+    Ok(match v {
+        Some(serde_json::Value::String(s)) => T::decode(&s),
+        Some(serde_json::Value::Number(n)) => T::decode(&n.to_string()),
+        _ => None,
+    })
+}
+
+/// Plasma::Types::Location: which screen edge a containment is attached to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum Location {
+    Floating,
+    Desktop,
+    FullScreen,
+    TopEdge,
+    BottomEdge,
+    LeftEdge,
+    RightEdge,
+}
+
+impl ConfigEnum for Location {
+    const VARIANTS: &'static [(Self, u32, &'static str)] = &[
+        (Location::Floating, 0, "floating"),
+        (Location::Desktop, 1, "desktop"),
+        (Location::FullScreen, 2, "full-screen"),
+        (Location::TopEdge, 3, "top-edge"),
+        (Location::BottomEdge, 4, "bottom-edge"),
+        (Location::LeftEdge, 5, "left-edge"),
+        (Location::RightEdge, 6, "right-edge"),
+    ];
+}
+
+impl Location {
+    /// Value for `panel.location` in Plasma scripting; `None` for
+    /// locations a panel cannot have.
+    pub fn script_name(self) -> Option<&'static str> {
+        match self {
+            Location::TopEdge => Some("top"),
+            Location::BottomEdge => Some("bottom"),
+            Location::LeftEdge => Some("left"),
+            Location::RightEdge => Some("right"),
+            Location::Floating => Some("floating"),
+            Location::Desktop | Location::FullScreen => None,
+        }
+    }
+
+    pub fn is_edge(self) -> bool {
+        matches!(
+            self,
+            Location::TopEdge | Location::BottomEdge | Location::LeftEdge | Location::RightEdge
+        )
+    }
+
+    /// "Bottom", "Left", ... for titles.
+    pub fn label(self) -> &'static str {
+        match self {
+            Location::Floating => "Floating",
+            Location::Desktop => "Desktop",
+            Location::FullScreen => "Full-screen",
+            Location::TopEdge => "Top",
+            Location::BottomEdge => "Bottom",
+            Location::LeftEdge => "Left",
+            Location::RightEdge => "Right",
+        }
+    }
+}
+
+/// Plasma::Types::FormFactor: the shape the containment lays widgets out in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum FormFactor {
+    Planar,
+    MediaCenter,
+    Horizontal,
+    Vertical,
+    Application,
+}
+
+impl ConfigEnum for FormFactor {
+    const VARIANTS: &'static [(Self, u32, &'static str)] = &[
+        (FormFactor::Planar, 0, "planar"),
+        (FormFactor::MediaCenter, 1, "media-center"),
+        (FormFactor::Horizontal, 2, "horizontal"),
+        (FormFactor::Vertical, 3, "vertical"),
+        (FormFactor::Application, 4, "application"),
+    ];
+}
+
+/// Plasma::Types::ImmutabilityType: whether the user may edit the containment.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum Immutability {
+    Mutable,
+    UserImmutable,
+    SystemImmutable,
+}
+
+impl ConfigEnum for Immutability {
+    const VARIANTS: &'static [(Self, u32, &'static str)] = &[
+        (Immutability::Mutable, 1, "mutable"),
+        (Immutability::UserImmutable, 2, "user-immutable"),
+        (Immutability::SystemImmutable, 4, "system-immutable"),
+    ];
+}
+
+/// Localized values of keys written as `Name[de]=...`: key → locale → value.
+pub type Localized = BTreeMap<String, BTreeMap<String, String>>;
+
+/// KConfig markers seen on a group and its keys.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct Markers {
+    /// Group carries `[$i]`, or inherits it from a parent group or the file
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub immutable: bool,
+
+    /// Keys written as `key[$i]=...`
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub immutable_keys: BTreeSet<String>,
+
+    /// Keys written as `key[$e]=...`; their values are stored unexpanded,
+    /// so `$HOME` still means the home directory of whoever restores them
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub expanded_keys: BTreeSet<String>,
+
+    /// Keys deleted with `key[$d]`
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub deleted_keys: BTreeSet<String>,
+
+    /// Values escaped differently from how KConfig writes them (`\x41`,
+    /// `\s` inside the text, ...): key → value as written after the `=`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub raw_values: BTreeMap<String, String>,
+}
+
+impl Markers {
+    pub fn is_empty(&self) -> bool {
+        self == &Markers::default()
+    }
+
+    pub fn is_key_locked(&self, key: &str) -> bool {
+        self.immutable || self.immutable_keys.contains(key)
+    }
+
+    /// `value` with its environment variables expanded, if `key` is an
+    /// `[$e]` key: what Plasma reads on this machine.
+    pub fn expand(&self, key: &str, value: &str) -> Option<String> {
+        self.expanded_keys
+            .contains(key)
+            .then(|| crate::kconfig::expand_env(value))
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct KWinScan {
     /// Path to the kwinrc that was scanned
     pub kwinrc: String,
@@ -74,19 +801,250 @@ pub struct KWinScan {
     /// Path to the kwinrulesrc that was scanned
     pub kwinrulesrc: String,
 
+    /// `Group/key` values that come from a system kwinrc rather than the user's, with that file
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub provenance: BTreeMap<String, String>,
+
     pub summary: KWinSummary,
+
+    /// Effects and scripts switched on or off in kwinrc `[Plugins]`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub plugins: Vec<KWinPlugin>,
+
+    /// kwinrulesrc rules in the order KWin applies them
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub window_rules: Vec<WindowRule>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[derive(Debug, Clone, Serialize, Deserialize, Default, JsonSchema)]
 pub struct KWinSummary {
     pub enabled_effects: Vec<String>,
     pub enabled_scripts: Vec<String>,
 
-    /// From [TabBox]
+    /// Effects switched off with `<id>Enabled=false`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub disabled_effects: Vec<String>,
+
+    /// Scripts switched off with `<id>Enabled=false`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub disabled_scripts: Vec<String>,
+
+    /// `[Plugins]` ids without metadata on this machine
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub unknown_plugins: Vec<String>,
+
+    /// From `[TabBox]`
     pub task_switcher: BTreeMap<String, String>,
 
-    /// From [TabBoxAlternative]
+    /// From `[TabBoxAlternative]`
     pub task_switcher_alternative: BTreeMap<String, String>,
 
+    /// From `[Desktops]`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub virtual_desktops: Option<VirtualDesktops>,
+
+    /// From `[org.kde.kdecoration2]`: decoration plugin (`library`), its
+    /// `theme` and the titlebar buttons
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub decoration: BTreeMap<String, String>,
+
     pub window_rules_count: usize,
 }
+
+/// Virtual desktops from kwinrc `[Desktops]`.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct VirtualDesktops {
+    /// `Rows` of the desktop grid in the pager and switcher
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rows: Option<u32>,
+
+    /// One entry per desktop (`Number` of them), in order
+    pub desktops: Vec<VirtualDesktop>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct VirtualDesktop {
+    /// `Id_<n>`: the UUID window rules and activities refer to the desktop by
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+
+    /// `Name_<n>`; KWin shows "Desktop <n>" when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+}
+
+/// A KWin effect or script named in kwinrc `[Plugins]` as `<id>Enabled=`.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct KWinPlugin {
+    /// KPlugin id
+    pub id: String,
+
+    /// `None` when no metadata was found for the id
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kind: Option<KWinPluginKind>,
+
+    /// Display name from the metadata
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+
+    /// The `[Plugins]` value
+    pub enabled: bool,
+
+    /// KPlugin `EnabledByDefault`: whether KWin runs it without a `[Plugins]` entry
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub enabled_by_default: Option<bool>,
+
+    /// metadata.json the plugin was resolved from; `None` for effects
+    /// compiled into KWin
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum KWinPluginKind {
+    Effect,
+    Script,
+}
+
+impl KWinPlugin {
+    /// The display name, or the id when there is none.
+    pub fn label(&self) -> &str {
+        self.name.as_deref().unwrap_or(&self.id)
+    }
+}
+
+/// One KWin window rule: a group of kwinrulesrc.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct WindowRule {
+    /// Group name: a UUID on Plasma 6, a number in older files
+    pub id: String,
+
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub description: String,
+
+    /// Window class (`wmclass`, `wmclassmatch`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub wmclass: Option<WindowMatch>,
+
+    /// Match the whole class, `resource class`, rather than the resource name (`wmclasscomplete`)
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub wmclass_complete: bool,
+
+    /// Window title (`title`, `titlematch`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<WindowMatch>,
+
+    /// Window role (`windowrole`, `windowrolematch`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub role: Option<WindowMatch>,
+
+    /// Host the window runs on (`clientmachine`, `clientmachinematch`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub machine: Option<WindowMatch>,
+
+    /// NET::WindowTypeMask of the window types the rule applies to (`types`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub types: Option<u32>,
+
+    /// What the rule sets, by kwinrulesrc key (`above`, `desktops`,
+    /// `position`, ...)
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub properties: BTreeMap<String, RuleProperty>,
+}
+
+/// A string a window rule matches on.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct WindowMatch {
+    pub value: String,
+
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        deserialize_with = "lenient"
+    )]
+    pub kind: Option<MatchKind>,
+}
+
+/// A window property set by a rule: `<key>=value` and `<key>rule=policy`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct RuleProperty {
+    #[serde(default)]
+    pub value: String,
+
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        deserialize_with = "lenient"
+    )]
+    pub policy: Option<RulePolicy>,
+}
+
+/// KWin Rules::StringMatch: how a window string is compared.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum MatchKind {
+    Unimportant,
+    Exact,
+    Substring,
+    Regex,
+}
+
+impl ConfigEnum for MatchKind {
+    const VARIANTS: &'static [(Self, u32, &'static str)] = &[
+        (MatchKind::Unimportant, 0, "unimportant"),
+        (MatchKind::Exact, 1, "exact"),
+        (MatchKind::Substring, 2, "substring"),
+        (MatchKind::Regex, 3, "regex"),
+    ];
+}
+
+/// KWin Rules::Type: when and how a rule property is applied.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum RulePolicy {
+    Unused,
+    DontAffect,
+    Force,
+    ApplyInitially,
+    Remember,
+    ApplyNow,
+    ForceTemporarily,
+}
+
+impl ConfigEnum for RulePolicy {
+    const VARIANTS: &'static [(Self, u32, &'static str)] = &[
+        (RulePolicy::Unused, 0, "unused"),
+        (RulePolicy::DontAffect, 1, "dont-affect"),
+        (RulePolicy::Force, 2, "force"),
+        (RulePolicy::ApplyInitially, 3, "apply-initially"),
+        (RulePolicy::Remember, 4, "remember"),
+        (RulePolicy::ApplyNow, 5, "apply-now"),
+        (RulePolicy::ForceTemporarily, 6, "force-temporarily"),
+    ];
+}
+
+impl WindowRule {
+    /// `firefox (exact)`-style summary of what the rule matches.
+    pub fn match_label(&self) -> String {
+        let mut parts = Vec::new();
+        for (what, m) in [
+            ("class", &self.wmclass),
+            ("title", &self.title),
+            ("role", &self.role),
+            ("machine", &self.machine),
+        ] {
+            if let Some(m) = m {
+                let kind = m.kind.map_or("?", |k| k.name());
+                if m.kind != Some(MatchKind::Unimportant) {
+                    parts.push(format!("{what} {} ({kind})", m.value));
+                }
+            }
+        }
+        if parts.is_empty() {
+            "any window".to_string()
+        } else {
+            parts.join(", ")
+        }
+    }
+}
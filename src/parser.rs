@@ -1,52 +1,13 @@
-use anyhow::{bail, Context, Result};
-use regex::Regex;
+use anyhow::{bail, Result};
 use std::collections::BTreeMap;
-use std::fs;
+use std::path::PathBuf;
 
-use crate::model::{Applet, Containment, Layout};
-
-fn tokenize_section(section: &str) -> Vec<String> {
-    let mut out = Vec::new();
-    let mut cur = String::new();
-    let mut in_bracket = false;
-    for ch in section.chars() {
-        match ch {
-            '[' => {
-                in_bracket = true;
-                cur.clear();
-            }
-            ']' => {
-                if in_bracket {
-                    out.push(cur.clone());
-                    in_bracket = false;
-                }
-            }
-            _ => {
-                if in_bracket {
-                    cur.push(ch);
-                }
-            }
-        }
-    }
-    out
-}
-
-#[derive(Debug, Default)]
-struct IniTree {
-    sections: BTreeMap<Vec<String>, BTreeMap<String, String>>,
-}
-
-impl IniTree {
-    fn insert_kv(&mut self, path: Vec<String>, k: String, v: String) {
-        self.sections.entry(path).or_default().insert(k, v);
-    }
-    fn section(&self, path: &[String]) -> Option<&BTreeMap<String, String>> {
-        self.sections.get(path)
-    }
-    fn iter_sections(&self) -> impl Iterator<Item = (&Vec<String>, &BTreeMap<String, String>)> {
-        self.sections.iter()
-    }
-}
+use crate::diagnostics::{Diagnostic, DiagnosticKind};
+use crate::kconfig::{self, format_path, IniTree};
+use crate::model::{
+    Applet, ConfigEnum, Containment, FormFactor, IconScreen, Immutability, Layout, Localized,
+    Location, Markers, Screens, SystrayItems, Wallpaper, SCHEMA_VERSION,
+};
 
 fn parse_applet_order(s: &str) -> Option<Vec<u32>> {
     // AppletOrder=546;552;551;...
@@ -67,109 +28,373 @@ fn parse_applet_order(s: &str) -> Option<Vec<u32>> {
     }
 }
 
-pub fn parse_appletsrc(path: &str) -> Result<Layout> {
-    let raw = fs::read_to_string(path).with_context(|| format!("reading {path}"))?;
-    let header_re = Regex::new(r"^\s*(\[.*\])\s*$")?;
+/// File name of the Plasma desktop layout under the config directories.
+pub const APPLETSRC: &str = "plasma-org.kde.plasma.desktop-appletsrc";
 
-    let mut tree = IniTree::default();
-    let mut current_path: Option<Vec<String>> = None;
+/// The system tray applet; its icons live in a separate containment.
+pub const SYSTRAY_APPLET: &str = "org.kde.plasma.systemtray";
 
-    for (lineno, line) in raw.lines().enumerate() {
-        let line = line.trim();
-        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
-            continue;
+/// Plugin of the containment behind a system tray applet.
+pub const SYSTRAY_CONTAINMENT: &str = "org.kde.plasma.private.systemtray";
+
+/// Top-level group of the mouse actions shared by all containments of a type.
+const ACTION_PLUGINS: &str = "ActionPlugins";
+
+/// KConfig string list: `a,b,c` with `\,` for a literal comma; empty
+/// entries dropped.
+fn split_list(s: &str) -> Vec<String> {
+    split_list_raw(s)
+        .into_iter()
+        .map(|x| x.trim().to_string())
+        .filter(|x| !x.is_empty())
+        .collect()
+}
+
+/// [`split_list`] keeping empty entries, for lists with positional fields.
+pub(crate) fn split_list_raw(s: &str) -> Vec<String> {
+    let mut out = vec![String::new()];
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some(',') => out.last_mut().unwrap().push(','),
+                Some(n) => {
+                    out.last_mut().unwrap().push('\\');
+                    out.last_mut().unwrap().push(n);
+                }
+                None => out.last_mut().unwrap().push('\\'),
+            },
+            ',' => out.push(String::new()),
+            _ => out.last_mut().unwrap().push(c),
         }
-        if let Some(cap) = header_re.captures(line) {
-            let sec = cap.get(1).unwrap().as_str();
-            let toks = tokenize_section(sec);
-            if toks.is_empty() {
-                bail!("bad section header at line {}: {}", lineno + 1, line);
+    }
+    out
+}
+
+/// Settings of wallpaper `plugin` from the `[Wallpaper]` groups of one
+/// containment, with the local paths they mention.
+fn parse_wallpaper(tree: &IniTree, plugin: &str, groups: &[&Vec<String>]) -> Wallpaper {
+    let mut wp = Wallpaper {
+        plugin: plugin.to_string(),
+        ..Wallpaper::default()
+    };
+    for path in groups.iter().filter(|p| p.len() >= 5 && p[3] == plugin) {
+        let kv = tree.section(path).cloned().unwrap_or_default();
+        let markers = tree.markers(path);
+        for (k, v) in &kv {
+            let v = markers.expand(k, v).unwrap_or_else(|| v.clone());
+            for item in split_list(&v) {
+                if let Some(local) = local_path(&item) {
+                    if !wp.files.contains(&local) {
+                        wp.files.push(local);
+                    }
+                }
             }
-            current_path = Some(toks);
-            continue;
         }
+        let group = path[4..].join("/");
+        if markers != Markers::default() {
+            wp.config_markers.insert(group.clone(), markers);
+        }
+        wp.config.insert(group, kv);
+    }
+    wp
+}
+
+/// `/abs/path` or `file:///abs/path`, without a trailing `/`.
+pub(crate) fn local_path(item: &str) -> Option<String> {
+    let p = item.strip_prefix("file://").unwrap_or(item);
+    if p.len() > 1 && p.starts_with('/') {
+        Some(p.trim_end_matches('/').to_string())
+    } else {
+        None
+    }
+}
+
+/// `[ScreenMapping] screenMapping=url,screen,activity,url,screen,activity,...`
+fn parse_icon_mapping(tree: &IniTree) -> Vec<IconScreen> {
+    let Some(raw) = tree
+        .group(&["ScreenMapping"])
+        .and_then(|g| g.get("screenMapping"))
+    else {
+        return Vec::new();
+    };
+    split_list_raw(raw)
+        .chunks_exact(3)
+        .filter_map(|c| {
+            Some(IconScreen {
+                url: c[0].clone(),
+                screen: c[1].trim().parse().ok()?,
+                activity: c[2].clone(),
+            })
+        })
+        .collect()
+}
+
+fn parse_systray_items(general: &BTreeMap<String, String>) -> SystrayItems {
+    let list = |key: &str| general.get(key).map(|v| split_list(v)).unwrap_or_default();
+    SystrayItems {
+        extra_items: list("extraItems"),
+        known_items: list("knownItems"),
+        shown_items: list("shownItems"),
+        hidden_items: list("hiddenItems"),
+    }
+}
 
-        let Some(path_tokens) = current_path.clone() else {
+/// Links each system tray applet to the containment named by its
+/// `SystrayContainmentId`, in both directions.
+pub(crate) fn link_systrays(containments: &mut [Containment]) {
+    let mut links = Vec::new();
+    for c in containments.iter() {
+        for a in &c.applets {
+            if a.plugin.as_deref() != Some(SYSTRAY_APPLET) {
+                continue;
+            }
+            let child = a
+                .config
+                .get("Configuration")
+                .and_then(|kv| kv.get("SystrayContainmentId"))
+                .and_then(|v| v.trim().parse::<u32>().ok());
+            if let Some(child) = child.filter(|&id| id != c.id) {
+                links.push((c.id, a.id, child));
+            }
+        }
+    }
+    for (cid, aid, child) in links {
+        let Some(tray) = containments.iter_mut().find(|c| c.id == child) else {
             continue;
         };
-        if let Some((k, v)) = line.split_once('=') {
-            tree.insert_kv(path_tokens, k.trim().to_string(), v.trim().to_string());
+        tray.parent_applet = Some(aid);
+        if let Some(a) = containments
+            .iter_mut()
+            .find(|c| c.id == cid)
+            .and_then(|c| c.applets.iter_mut().find(|a| a.id == aid))
+        {
+            a.child_containment = Some(child);
+        }
+    }
+}
+
+/// Reports `AppletOrder` entries that are not applet ids, pointing at the entry itself.
+fn check_applet_order(tree: &IniTree, path: &[String], src: &Source, diags: &mut Vec<Diagnostic>) {
+    let Some(value) = tree.section(path).and_then(|s| s.get("AppletOrder")) else {
+        return;
+    };
+    let Some((line, col)) = tree.value_span(path, "AppletOrder") else {
+        return;
+    };
+    let mut offset = 0;
+    for part in value.split(';') {
+        let t = part.trim();
+        if !t.is_empty() && t.parse::<u32>().is_err() {
+            let lead = part.len() - part.trim_start().len();
+            diags.push(src.diagnostic(
+                DiagnosticKind::BadAppletOrder,
+                format!(
+                    "`{t}` in AppletOrder of {} is not an applet id; skipped",
+                    format_path(path)
+                ),
+                line,
+                col + value[..offset + lead].chars().count(),
+                t.chars().count(),
+            ));
         }
+        offset += part.len() + 1;
     }
+}
 
-    let mut containment_ids: Vec<u32> = Vec::new();
-    for (path, _) in tree.iter_sections() {
-        if path.len() >= 2 && path[0] == "Containments" {
-            if let Ok(id) = path[1].parse::<u32>() {
-                if !containment_ids.contains(&id) {
-                    containment_ids.push(id);
+/// The file being parsed, for building diagnostics after the tree exists.
+struct Source<'a> {
+    file: &'a str,
+    raw: &'a str,
+}
+
+impl Source<'_> {
+    fn diagnostic(
+        &self,
+        kind: DiagnosticKind,
+        message: String,
+        line: usize,
+        column: usize,
+        len: usize,
+    ) -> Diagnostic {
+        let snippet = self.raw.lines().nth(line.saturating_sub(1)).unwrap_or("");
+        Diagnostic::new(kind, message, self.file, line, snippet, column, len)
+    }
+
+    /// `None` when the group only exists in a system file.
+    fn bad_id(&self, tree: &IniTree, path: &[String], what: &str) -> Option<Diagnostic> {
+        let line = tree.header_line(path)?;
+        let snippet = self.raw.lines().nth(line.saturating_sub(1)).unwrap_or("");
+        let id = path.last().map(String::as_str).unwrap_or("");
+        let col = snippet.find(&format!("[{id}]")).map_or(1, |i| i + 2);
+        Some(self.diagnostic(
+            DiagnosticKind::BadId,
+            format!(
+                "{what} id `{id}` in {} is not a number; group ignored",
+                format_path(path)
+            ),
+            line,
+            col,
+            id.chars().count(),
+        ))
+    }
+}
+
+/// Parses exactly the appletsrc at `path`, without the XDG cascade.
+pub fn parse_appletsrc(path: &str) -> Result<Layout> {
+    load_appletsrc(path, &[])
+}
+
+/// Loads the appletsrc at `path` on top of the copies in `config_dirs`,
+/// merged the way KConfig does. Values that do not come from `path`
+/// itself are listed in each containment's and applet's `provenance`.
+pub fn load_appletsrc(path: &str, config_dirs: &[PathBuf]) -> Result<Layout> {
+    let mut diags = Vec::new();
+    let cascade = kconfig::load_cascade(APPLETSRC, path, config_dirs, &mut diags)?;
+    if cascade.files.is_empty() {
+        bail!("reading {path}: no such file");
+    }
+    let tree = &cascade.effective;
+    let src = Source {
+        file: path,
+        raw: &cascade.user_raw,
+    };
+
+    // One pass over the tree: every group below `[Containments]` goes into
+    // the bucket of the containment and applet it belongs to.
+    let mut buckets: BTreeMap<u32, ContainmentGroups> = BTreeMap::new();
+    for (spath, _) in tree.iter_sections() {
+        if spath.len() < 2 || spath[0] != "Containments" {
+            continue;
+        }
+        let Ok(cid) = spath[1].parse::<u32>() else {
+            if spath.len() == 2 {
+                diags.extend(src.bad_id(tree, spath, "containment"));
+            }
+            continue;
+        };
+        let bucket = buckets.entry(cid).or_default();
+        match spath.get(2).map(String::as_str) {
+            None => continue,
+            Some("Wallpaper") => {
+                if spath.len() >= 4 {
+                    bucket.wallpaper.push(spath);
                 }
+                continue;
+            }
+            Some("Applets") if spath.len() >= 4 => {}
+            Some("Applets") => continue,
+            Some(_) => {
+                bucket.config.push(spath);
+                continue;
             }
         }
+        let Ok(aid) = spath[3].parse::<u32>() else {
+            if spath.len() == 4 {
+                diags.extend(src.bad_id(tree, spath, "applet"));
+            }
+            continue;
+        };
+        let applet = bucket.applets.entry(aid).or_default();
+        if spath.len() >= 5 && spath[4] == "Configuration" {
+            applet.config.push(spath);
+        } else if spath.len() >= 5 {
+            applet.other.push(spath);
+        }
     }
-    containment_ids.sort_unstable();
 
-    let mut containments = Vec::new();
+    let mut containments = Vec::with_capacity(buckets.len());
 
-    for cid in containment_ids {
-        let cpath = vec!["Containments".to_string(), cid.to_string()];
+    for (cid, bucket) in buckets {
+        let cpath = containment_path(cid);
         let meta = tree.section(&cpath).cloned().unwrap_or_default();
+        let localized = tree.localized(&cpath);
+        let mut markers = tree.markers(&cpath);
+        markers.immutable = tree.is_immutable(&cpath);
 
         let plugin = meta.get("plugin").cloned();
-        let is_panel = plugin.as_deref() == Some("org.kde.panel");
+        let location = meta.get("location").and_then(|v| Location::decode(v));
+        let form_factor = meta.get("formfactor").and_then(|v| FormFactor::decode(v));
+        let immutability = meta
+            .get("immutability")
+            .and_then(|v| Immutability::decode(v));
+        // Third-party panel containments only give themselves away by sitting
+        // on an edge with a line form factor, like the tray does.
+        let is_panel = plugin.as_deref() == Some("org.kde.panel")
+            || (plugin.as_deref() != Some(SYSTRAY_CONTAINMENT)
+                && location.is_some_and(Location::is_edge)
+                && matches!(
+                    form_factor,
+                    Some(FormFactor::Horizontal | FormFactor::Vertical)
+                ));
 
-        let applet_order = meta.get("AppletOrder").and_then(|s| parse_applet_order(s));
+        let mut provenance = provenance_of(&cascade, &cpath, None);
+        let cgroups = read_groups(&cascade, &bucket.config, |p| p[2..].join("/"));
+        provenance.extend(cgroups.provenance);
 
-        let mut applet_ids: Vec<u32> = Vec::new();
-        for (path, _) in tree.iter_sections() {
-            if path.len() >= 4
-                && path[0] == "Containments"
-                && path[1] == cid.to_string()
-                && path[2] == "Applets"
-            {
-                if let Ok(aid) = path[3].parse::<u32>() {
-                    if !applet_ids.contains(&aid) {
-                        applet_ids.push(aid);
-                    }
-                }
+        // Plasma 6 keeps the panel widget order in `[General]`.
+        let applet_order = meta
+            .get("AppletOrder")
+            .or_else(|| cgroups.config.get("General")?.get("AppletOrder"))
+            .and_then(|s| parse_applet_order(s));
+        check_applet_order(tree, &cpath, &src, &mut diags);
+        check_applet_order(tree, &group_path(cid, "General"), &src, &mut diags);
+
+        let systray = if plugin.as_deref() == Some(SYSTRAY_CONTAINMENT) {
+            let mut gpath = cpath.clone();
+            gpath.push("General".to_string());
+            tree.section(&gpath).map(parse_systray_items)
+        } else {
+            None
+        };
+
+        let wallpaper = match meta.get("wallpaperplugin") {
+            Some(wp) if !is_panel && plugin.as_deref() != Some(SYSTRAY_CONTAINMENT) => {
+                Some(parse_wallpaper(tree, wp, &bucket.wallpaper))
             }
-        }
-        applet_ids.sort_unstable();
+            _ => None,
+        };
 
-        let mut applets = Vec::new();
-        for aid in applet_ids {
-            let apath = vec![
-                "Containments".to_string(),
-                cid.to_string(),
-                "Applets".to_string(),
-                aid.to_string(),
-            ];
+        let mut applets = Vec::with_capacity(bucket.applets.len());
+        for (aid, groups) in bucket.applets {
+            let apath = applet_path(cid, aid);
             let ameta = tree.section(&apath).cloned().unwrap_or_default();
             let aplug = ameta.get("plugin").cloned();
-
-            let mut config: BTreeMap<String, BTreeMap<String, String>> = BTreeMap::new();
-            for (spath, skv) in tree.iter_sections() {
-                if spath.len() >= 5
-                    && spath[0] == "Containments"
-                    && spath[1] == cid.to_string()
-                    && spath[2] == "Applets"
-                    && spath[3] == aid.to_string()
-                    && spath[4] == "Configuration"
-                {
-                    let group = if spath.len() == 5 {
-                        "Configuration".to_string()
-                    } else {
-                        spath[5..].join("/")
-                    };
-                    config.insert(group, skv.clone());
+            let alocalized = tree.localized(&apath);
+            let mut amarkers = tree.markers(&apath);
+            amarkers.immutable = tree.is_immutable(&apath);
+            let mut aprovenance = provenance_of(&cascade, &apath, None);
+            let agroups = read_groups(&cascade, &groups.config, |p| {
+                if p.len() == 5 {
+                    "Configuration".to_string()
+                } else {
+                    p[5..].join("/")
                 }
-            }
+            });
+            aprovenance.extend(agroups.provenance);
+            let others = read_groups(&cascade, &groups.other, |p| p[4..].join("/"));
+            aprovenance.extend(others.provenance);
+            let shortcut = others
+                .config
+                .get("Shortcuts")
+                .and_then(|kv| kv.get("global"))
+                .filter(|v| !v.trim().is_empty())
+                .cloned();
 
             applets.push(Applet {
                 id: aid,
                 plugin: aplug,
                 meta: ameta,
-                config,
+                localized: alocalized,
+                config: agroups.config,
+                config_localized: agroups.localized,
+                markers: amarkers,
+                config_markers: agroups.markers,
+                provenance: aprovenance,
+                groups: others.config,
+                groups_localized: others.localized,
+                shortcut,
+                child_containment: None,
             });
         }
 
@@ -178,14 +403,596 @@ pub fn parse_appletsrc(path: &str) -> Result<Layout> {
             plugin,
             is_panel,
             meta,
+            location,
+            form_factor,
+            immutability,
+            localized,
+            markers,
+            provenance,
             applet_order,
             applets,
+            parent_applet: None,
+            systray,
+            geometry: None,
+            wallpaper,
+            config: cgroups.config,
+            config_localized: cgroups.localized,
+            config_markers: cgroups.markers,
         });
     }
+    link_systrays(&mut containments);
+
+    let screens = Screens {
+        icon_mapping: parse_icon_mapping(tree),
+        ..Screens::default()
+    };
 
     Ok(Layout {
+        schema_version: SCHEMA_VERSION,
         source_file: path.to_string(),
+        sources: cascade.files.clone(),
         containments,
+        activities: Vec::new(),
+        action_plugins: parse_action_plugins(tree),
+        screens,
         kwin: None,
+        diagnostics: diags,
+        syntax: cascade.user,
     })
 }
+
+/// Groups below one `[Containments][N]`, keyed by numeric id.
+#[derive(Default)]
+struct ContainmentGroups<'a> {
+    applets: BTreeMap<u32, AppletGroups<'a>>,
+    /// `[Wallpaper][<plugin>]...` groups, for every plugin
+    wallpaper: Vec<&'a Vec<String>>,
+    /// Every other group (`[General]`, `[ConfigDialog]`, ...)
+    config: Vec<&'a Vec<String>>,
+}
+
+/// Groups below one `[Applets][N]`.
+#[derive(Default)]
+struct AppletGroups<'a> {
+    /// `[Configuration]` and its subgroups
+    config: Vec<&'a Vec<String>>,
+    /// Every other subgroup (`[Shortcuts]`, ...)
+    other: Vec<&'a Vec<String>>,
+}
+
+/// Configuration groups of a containment or applet, keyed by group name.
+#[derive(Default)]
+struct ConfigGroups {
+    config: BTreeMap<String, BTreeMap<String, String>>,
+    localized: BTreeMap<String, Localized>,
+    markers: BTreeMap<String, Markers>,
+    provenance: BTreeMap<String, String>,
+}
+
+/// Reads the groups at `paths`, naming each with `name`.
+fn read_groups(
+    cascade: &kconfig::Cascade,
+    paths: &[&Vec<String>],
+    name: impl Fn(&[String]) -> String,
+) -> ConfigGroups {
+    let tree = &cascade.effective;
+    let mut out = ConfigGroups::default();
+    for spath in paths {
+        let group = name(spath);
+        let mut m = tree.markers(spath);
+        m.immutable = tree.is_immutable(spath);
+        if m != Markers::default() {
+            out.markers.insert(group.clone(), m);
+        }
+        let loc = tree.localized(spath);
+        if !loc.is_empty() {
+            out.localized.insert(group.clone(), loc);
+        }
+        out.provenance
+            .extend(provenance_of(cascade, spath, Some(&group)));
+        let skv = tree.section(spath).cloned().unwrap_or_default();
+        out.config.insert(group, skv);
+    }
+    out
+}
+
+/// Global `[ActionPlugins]` groups, keyed by path below it joined with `/`.
+fn parse_action_plugins(tree: &IniTree) -> BTreeMap<String, BTreeMap<String, String>> {
+    tree.iter_sections()
+        .filter(|(p, _)| p.len() >= 2 && p[0] == ACTION_PLUGINS)
+        .map(|(p, kv)| (p[1..].join("/"), kv.clone()))
+        .collect()
+}
+
+/// Keys of the group at `path` whose value came from a system file, prefixed
+/// with `group/` for applet configuration groups.
+fn provenance_of(
+    cascade: &kconfig::Cascade,
+    path: &[String],
+    group: Option<&str>,
+) -> BTreeMap<String, String> {
+    let mut out = BTreeMap::new();
+    for key in cascade
+        .provenance
+        .get(path)
+        .into_iter()
+        .flat_map(|p| p.keys())
+    {
+        if let Some(file) = cascade.non_user_source(path, key) {
+            let key = match group {
+                Some(g) => format!("{g}/{key}"),
+                None => key.clone(),
+            };
+            out.insert(key, file.clone());
+        }
+    }
+    out
+}
+
+fn containment_path(cid: u32) -> Vec<String> {
+    vec!["Containments".to_string(), cid.to_string()]
+}
+
+fn group_path(cid: u32, group: &str) -> Vec<String> {
+    let mut p = containment_path(cid);
+    p.extend(group.split('/').map(str::to_string));
+    p
+}
+
+fn action_plugins_path(group: &str) -> Vec<String> {
+    let mut p = vec![ACTION_PLUGINS.to_string()];
+    p.extend(group.split('/').map(str::to_string));
+    p
+}
+
+fn applet_path(cid: u32, aid: u32) -> Vec<String> {
+    let mut p = containment_path(cid);
+    p.push("Applets".to_string());
+    p.push(aid.to_string());
+    p
+}
+
+fn wallpaper_path(cid: u32, plugin: &str, group: &str) -> Vec<String> {
+    let mut p = containment_path(cid);
+    p.push("Wallpaper".to_string());
+    p.push(plugin.to_string());
+    p.extend(group.split('/').map(str::to_string));
+    p
+}
+
+fn applet_group_path(cid: u32, aid: u32, group: &str) -> Vec<String> {
+    let mut p = applet_path(cid, aid);
+    p.extend(group.split('/').map(str::to_string));
+    p
+}
+
+fn config_path(cid: u32, aid: u32, group: &str) -> Vec<String> {
+    let mut p = applet_path(cid, aid);
+    p.push("Configuration".to_string());
+    if group != "Configuration" {
+        p.extend(group.split('/').map(str::to_string));
+    }
+    p
+}
+
+/// Replaces the keys of the group at `path` with `kv` and `localized`,
+/// touching only lines that differ. New lines keep the escaping recorded
+/// in `markers`.
+///
+/// Keys for which `inherited` is true came from a system file (or were
+/// overridden by one) and are neither written nor removed.
+fn sync_section(
+    tree: &mut IniTree,
+    path: &[String],
+    kv: &BTreeMap<String, String>,
+    localized: &Localized,
+    markers: &Markers,
+    inherited: &dyn Fn(&str) -> bool,
+) {
+    let stale: Vec<String> = tree
+        .section(path)
+        .map(|s| {
+            s.keys()
+                .filter(|k| !kv.contains_key(*k) && !inherited(k))
+                .cloned()
+                .collect()
+        })
+        .unwrap_or_default();
+    for k in stale {
+        tree.remove(path, &k);
+    }
+    for (k, v) in kv {
+        if !inherited(k) {
+            tree.set(path, k, v, markers);
+        }
+    }
+
+    let mut stale_localized = Vec::new();
+    for (k, locales) in tree.localized(path) {
+        for l in locales.keys() {
+            if !localized.get(&k).is_some_and(|m| m.contains_key(l))
+                && !inherited(&format!("{k}[{l}]"))
+            {
+                stale_localized.push((k.clone(), l.clone()));
+            }
+        }
+    }
+    for (k, l) in stale_localized {
+        tree.remove_localized(path, &k, &l);
+    }
+    for (k, locales) in localized {
+        for (l, v) in locales {
+            if !inherited(&format!("{k}[{l}]")) {
+                tree.set_localized(path, k, l, v);
+            }
+        }
+    }
+}
+
+/// Whether the group for `id` was deleted from the model. Groups with an id
+/// that is not a number never made it into the model and are kept.
+fn is_removed(id: &str, ids: &[u32]) -> bool {
+    id.parse::<u32>().is_ok_and(|n| !ids.contains(&n))
+}
+
+/// Serializes `layout` as an appletsrc.
+///
+/// When the layout was produced by [`parse_appletsrc`] the original syntax tree
+/// is reused: an unchanged layout comes back byte-identical, and edits only
+/// touch the lines (and groups) they affect. Groups the model does not cover
+/// (`[ScreenMapping]`, settings of inactive wallpaper plugins, ...) are left alone, and
+/// values inherited from system files (see `provenance`) are not written.
+pub fn write_appletsrc(layout: &Layout) -> String {
+    let mut tree = layout.syntax.clone().unwrap_or_default();
+
+    let cids: Vec<u32> = layout.containments.iter().map(|c| c.id).collect();
+    tree.remove_groups(|p| p.len() >= 2 && p[0] == "Containments" && is_removed(&p[1], &cids));
+
+    for c in &layout.containments {
+        let cpath = containment_path(c.id);
+        let mut meta = c.meta.clone();
+        if let Some(p) = &c.plugin {
+            meta.insert("plugin".to_string(), p.clone());
+        }
+        if let Some(wp) = &c.wallpaper {
+            meta.insert("wallpaperplugin".to_string(), wp.plugin.clone());
+        }
+        let mut config = c.config.clone();
+        if let Some(order) = &c.applet_order {
+            // Written where the file had it: the containment section
+            // (Plasma 5) or `[General]` (Plasma 6).
+            let in_general = !meta.contains_key("AppletOrder")
+                && config
+                    .get("General")
+                    .is_some_and(|g| g.contains_key("AppletOrder"));
+            let holder = match config.get_mut("General") {
+                Some(general) if in_general => general,
+                _ => &mut meta,
+            };
+            let current = holder
+                .get("AppletOrder")
+                .and_then(|s| parse_applet_order(s));
+            if current.as_ref() != Some(order) {
+                let joined = order
+                    .iter()
+                    .map(|x| x.to_string())
+                    .collect::<Vec<_>>()
+                    .join(";");
+                holder.insert("AppletOrder".to_string(), joined);
+            }
+        }
+        sync_section(&mut tree, &cpath, &meta, &c.localized, &c.markers, &|k| {
+            c.provenance.contains_key(k)
+        });
+
+        let groups: Vec<Vec<String>> = config.keys().map(|g| group_path(c.id, g)).collect();
+        tree.remove_groups(|p| {
+            p.len() >= 3
+                && p[..2] == cpath[..]
+                && p[2] != "Applets"
+                && p[2] != "Wallpaper"
+                && !groups.iter().any(|g| g == p)
+        });
+        for (group, kv) in &config {
+            let localized = c.config_localized.get(group).cloned().unwrap_or_default();
+            let markers = c.config_markers.get(group).cloned().unwrap_or_default();
+            sync_section(
+                &mut tree,
+                &group_path(c.id, group),
+                kv,
+                &localized,
+                &markers,
+                &|k| c.provenance.contains_key(&format!("{group}/{k}")),
+            );
+        }
+
+        // Settings of other wallpaper plugins are kept as they are.
+        if let Some(wp) = &c.wallpaper {
+            let groups: Vec<Vec<String>> = wp
+                .config
+                .keys()
+                .map(|g| wallpaper_path(c.id, &wp.plugin, g))
+                .collect();
+            tree.remove_groups(|p| {
+                p.len() >= 5
+                    && p[..2] == cpath[..]
+                    && p[2] == "Wallpaper"
+                    && p[3] == wp.plugin
+                    && !groups.iter().any(|g| g == p)
+            });
+            for (group, kv) in &wp.config {
+                let markers = wp.config_markers.get(group).cloned().unwrap_or_default();
+                sync_section(
+                    &mut tree,
+                    &wallpaper_path(c.id, &wp.plugin, group),
+                    kv,
+                    &Localized::new(),
+                    &markers,
+                    &|_| false,
+                );
+            }
+        }
+
+        let aids: Vec<u32> = c.applets.iter().map(|a| a.id).collect();
+        tree.remove_groups(|p| {
+            p.len() >= 4 && p[..2] == cpath[..] && p[2] == "Applets" && is_removed(&p[3], &aids)
+        });
+
+        for a in &c.applets {
+            let apath = applet_path(c.id, a.id);
+            let mut ameta = a.meta.clone();
+            if let Some(p) = &a.plugin {
+                ameta.insert("plugin".to_string(), p.clone());
+            }
+            sync_section(&mut tree, &apath, &ameta, &a.localized, &a.markers, &|k| {
+                a.provenance.contains_key(k)
+            });
+
+            let groups: Vec<Vec<String>> = a
+                .config
+                .keys()
+                .map(|g| config_path(c.id, a.id, g))
+                .collect();
+            tree.remove_groups(|p| {
+                p.len() >= 5
+                    && p[..4] == apath[..]
+                    && p[4] == "Configuration"
+                    && !groups.iter().any(|g| g == p)
+            });
+            for (group, kv) in &a.config {
+                let localized = a.config_localized.get(group).cloned().unwrap_or_default();
+                let markers = a.config_markers.get(group).cloned().unwrap_or_default();
+                sync_section(
+                    &mut tree,
+                    &config_path(c.id, a.id, group),
+                    kv,
+                    &localized,
+                    &markers,
+                    &|k| a.provenance.contains_key(&format!("{group}/{k}")),
+                );
+            }
+
+            let mut other = a.groups.clone();
+            let shortcuts = other.entry("Shortcuts".to_string()).or_default();
+            match &a.shortcut {
+                Some(sc) => {
+                    shortcuts.insert("global".to_string(), sc.clone());
+                }
+                None => {
+                    shortcuts.remove("global");
+                }
+            }
+            // Dropping the shortcut drops the group, unless it was empty already.
+            if shortcuts.is_empty() && !a.groups.get("Shortcuts").is_some_and(|g| g.is_empty()) {
+                other.remove("Shortcuts");
+            }
+            let groups: Vec<Vec<String>> = other
+                .keys()
+                .map(|g| applet_group_path(c.id, a.id, g))
+                .collect();
+            tree.remove_groups(|p| {
+                p.len() >= 5
+                    && p[..4] == apath[..]
+                    && p[4] != "Configuration"
+                    && !groups.iter().any(|g| g == p)
+            });
+            for (group, kv) in &other {
+                let localized = a.groups_localized.get(group).cloned().unwrap_or_default();
+                sync_section(
+                    &mut tree,
+                    &applet_group_path(c.id, a.id, group),
+                    kv,
+                    &localized,
+                    &Markers::default(),
+                    &|k| a.provenance.contains_key(&format!("{group}/{k}")),
+                );
+            }
+        }
+    }
+
+    let groups: Vec<Vec<String>> = layout
+        .action_plugins
+        .keys()
+        .map(|g| action_plugins_path(g))
+        .collect();
+    tree.remove_groups(|p| {
+        p.len() >= 2 && p[0] == ACTION_PLUGINS && !groups.iter().any(|g| g == p)
+    });
+    for (group, kv) in &layout.action_plugins {
+        sync_section(
+            &mut tree,
+            &action_plugins_path(group),
+            kv,
+            &Localized::new(),
+            &Markers::default(),
+            &|_| false,
+        );
+    }
+
+    tree.serialize()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diagnostics;
+    use std::fs;
+    #[test]
+    fn localized_keys_survive_layout_json() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("appletsrc");
+        let raw = "[Containments][1]
+plugin=org.kde.panel
+
+[Containments][1][Applets][2]
+plugin=org.kde.plasma.kickoff
+
+[Containments][1][Applets][2][Configuration][General]
+title=Start
+title[de]=Anwendungen
+title[fr_FR]=Démarrer
+";
+        fs::write(&file, raw).unwrap();
+        let layout = parse_appletsrc(file.to_str().unwrap()).unwrap();
+        let json = serde_json::to_string(&layout).unwrap();
+        let back: Layout = serde_json::from_str(&json).unwrap();
+        let kickoff = &back.containments[0].applets[0];
+        assert_eq!(kickoff.config["General"].len(), 1);
+        let titles = &kickoff.config_localized["General"]["title"];
+        assert_eq!(titles["de"], "Anwendungen");
+        assert_eq!(titles["fr_FR"], "Démarrer");
+        assert_eq!(write_appletsrc(&back), raw);
+    }
+
+    #[test]
+    fn diagnostics_point_at_line_and_column_in_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("appletsrc");
+        fs::write(
+            &file,
+            "[Containments][1]
+plugin=org.kde.panel
+garbage
+
+[Containments][foo]
+  plugin=x
+[Containments][1]
+  plugin=org.kde.desktop
+",
+        )
+        .unwrap();
+        let mut layout = parse_appletsrc(file.to_str().unwrap()).unwrap();
+        diagnostics::sort(&mut layout.diagnostics);
+        let found: Vec<_> = layout
+            .diagnostics
+            .iter()
+            .map(|d| (d.kind, d.line, d.column))
+            .collect();
+        assert_eq!(
+            found,
+            [
+                (DiagnosticKind::MissingEquals, 3, 1),
+                (DiagnosticKind::BadId, 5, 16),
+                (DiagnosticKind::DuplicateKey, 8, 3),
+            ]
+        );
+    }
+
+    #[test]
+    fn bad_applet_order_entry_is_reported_where_it_is() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("appletsrc");
+        fs::write(
+            &file,
+            "[Containments][1]\nplugin=org.kde.panel\nAppletOrder=2; x;3\n",
+        )
+        .unwrap();
+        let layout = parse_appletsrc(file.to_str().unwrap()).unwrap();
+        let [d] = &layout.diagnostics[..] else {
+            panic!("{:?}", layout.diagnostics);
+        };
+        assert_eq!(
+            (d.kind, d.line, d.column, d.len),
+            (DiagnosticKind::BadAppletOrder, 3, 16, 1)
+        );
+        let rendered = d.to_string();
+        assert!(
+            rendered.starts_with("warning[bad-applet-order]: "),
+            "{rendered}"
+        );
+        assert!(
+            rendered.ends_with(&format!(
+                " --> {}:3:16\n  |\n3 | AppletOrder=2; x;3\n  |                ^",
+                file.display()
+            )),
+            "{rendered}"
+        );
+
+        assert!(diagnostics::report(&layout.diagnostics, false).is_ok());
+        assert!(diagnostics::report(&layout.diagnostics, true).is_err());
+    }
+
+    #[test]
+    fn locks_reach_the_model() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("appletsrc");
+        fs::write(
+            &file,
+            "[Containments][1][$i]
+plugin=org.kde.panel
+
+[Containments][2]
+plugin=org.kde.panel
+
+[Containments][2][Applets][3]
+plugin=org.kde.plasma.kickoff
+
+[Containments][2][Applets][3][Configuration][General]
+icon[$i]=start-here-kde
+label=Menu
+",
+        )
+        .unwrap();
+        let layout = parse_appletsrc(file.to_str().unwrap()).unwrap();
+        assert!(layout.containments[0].is_locked());
+        let panel = &layout.containments[1];
+        assert!(!panel.is_locked());
+        assert!(panel.applets[0].is_config_key_locked("General", "icon"));
+        assert!(!panel.applets[0].is_config_key_locked("General", "label"));
+    }
+
+    #[test]
+    fn provenance_names_the_system_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let xdg = vec![dir.path().join("xdg")];
+        fs::create_dir_all(&xdg[0]).unwrap();
+        fs::write(
+            xdg[0].join(APPLETSRC),
+            "[Containments][1]
+location=4
+plugin=org.kde.panel
+
+[Containments][1][Applets][2]
+plugin=org.kde.plasma.kickoff
+
+[Containments][1][Applets][2][Configuration][General]
+icon=start-here
+",
+        )
+        .unwrap();
+        let user = dir.path().join(APPLETSRC);
+        fs::write(&user, "[Containments][1]\nlocation=3\n").unwrap();
+
+        let layout = load_appletsrc(user.to_str().unwrap(), &xdg).unwrap();
+        let system = xdg[0].join(APPLETSRC).display().to_string();
+        let panel = &layout.containments[0];
+        assert_eq!(panel.meta["location"], "3");
+        assert_eq!(panel.provenance.get("plugin"), Some(&system));
+        assert!(!panel.provenance.contains_key("location"));
+        assert_eq!(
+            panel.applets[0].provenance.get("General/icon"),
+            Some(&system)
+        );
+        assert_eq!(layout.sources, [system, user.display().to_string()]);
+    }
+}
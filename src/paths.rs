@@ -0,0 +1,103 @@
+use std::path::{Path, PathBuf};
+
+use crate::parser::APPLETSRC;
+
+/// Where config and data files are looked up.
+///
+/// Built from the XDG base directory variables, or rooted at another home
+/// directory (`--root`) such as a mounted backup, another user's home or a
+/// CI fixture.
+#[derive(Debug, Clone)]
+pub struct Paths {
+    /// `$XDG_CONFIG_HOME`, default `~/.config`
+    pub config_home: PathBuf,
+    /// `$XDG_DATA_HOME`, default `~/.local/share`
+    pub data_home: PathBuf,
+    /// `$XDG_CONFIG_DIRS` (default `/etc/xdg`), most important first
+    pub config_dirs: Vec<PathBuf>,
+    /// `$XDG_DATA_DIRS` (default `/usr/local/share:/usr/share`), most important first
+    pub data_dirs: Vec<PathBuf>,
+}
+
+impl Paths {
+    /// From `$XDG_*` and the home directory, with the spec's defaults.
+    pub fn from_env() -> Self {
+        let home = dirs::home_dir().unwrap_or_else(|| PathBuf::from("."));
+        Paths {
+            config_home: env_dir("XDG_CONFIG_HOME").unwrap_or_else(|| home.join(".config")),
+            data_home: env_dir("XDG_DATA_HOME").unwrap_or_else(|| home.join(".local/share")),
+            config_dirs: env_dirs("XDG_CONFIG_DIRS", &["/etc/xdg"]),
+            data_dirs: env_dirs("XDG_DATA_DIRS", &["/usr/local/share", "/usr/share"]),
+        }
+    }
+
+    /// Treats `root` as the home directory and ignores the XDG variables.
+    ///
+    /// The system config cascade is off: `/etc/xdg` belongs to this machine,
+    /// not to the one `root` came from. System data dirs are kept, since they
+    /// are only used to look up package metadata.
+    pub fn with_root(root: &Path) -> Self {
+        Paths {
+            config_home: root.join(".config"),
+            data_home: root.join(".local/share"),
+            config_dirs: Vec::new(),
+            data_dirs: env_dirs("XDG_DATA_DIRS", &["/usr/local/share", "/usr/share"]),
+        }
+    }
+
+    /// `name` under the user config directory.
+    pub fn config_file(&self, name: &str) -> String {
+        self.config_home.join(name).display().to_string()
+    }
+
+    pub fn appletsrc(&self) -> String {
+        self.config_file(APPLETSRC)
+    }
+
+    pub fn kwinrc(&self) -> String {
+        self.config_file("kwinrc")
+    }
+
+    pub fn kwinrulesrc(&self) -> String {
+        self.config_file("kwinrulesrc")
+    }
+
+    pub fn plasmashellrc(&self) -> String {
+        self.config_file("plasmashellrc")
+    }
+
+    /// `<data_home>/<rel>`, e.g. `plasma/plasmoids/<id>`.
+    pub fn user_data(&self, rel: &str) -> PathBuf {
+        self.data_home.join(rel)
+    }
+
+    /// `<dir>/<rel>` for every system data dir that has it.
+    pub fn system_data(&self, rel: &str) -> Vec<PathBuf> {
+        self.data_dirs
+            .iter()
+            .map(|d| d.join(rel))
+            .filter(|p| p.exists())
+            .collect()
+    }
+}
+
+/// XDG requires absolute paths; relative values are ignored.
+fn env_dir(var: &str) -> Option<PathBuf> {
+    std::env::var_os(var)
+        .map(PathBuf::from)
+        .filter(|p| p.is_absolute())
+}
+
+fn env_dirs(var: &str, default: &[&str]) -> Vec<PathBuf> {
+    let dirs: Vec<PathBuf> = std::env::var(var)
+        .unwrap_or_default()
+        .split(':')
+        .map(PathBuf::from)
+        .filter(|p| p.is_absolute())
+        .collect();
+    if dirs.is_empty() {
+        default.iter().map(PathBuf::from).collect()
+    } else {
+        dirs
+    }
+}
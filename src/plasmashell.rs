@@ -0,0 +1,96 @@
+use anyhow::Result;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use crate::kconfig;
+use crate::model::{
+    Layout, PanelAlignment, PanelGeometry, PanelHiding, PanelLengthMode, PanelSize,
+};
+
+/// Reads plasmashellrc (through the XDG cascade over `config_dirs`):
+/// `[PlasmaViews][Panel N]` is attached to panel `N` of `layout` and
+/// `[ScreenConnectors]` fills `layout.screens.connectors`.
+///
+/// A missing plasmashellrc is not an error: panels keep `geometry: None`.
+pub fn attach_plasmashellrc(
+    layout: &mut Layout,
+    plasmashellrc_path: &str,
+    config_dirs: &[PathBuf],
+) -> Result<()> {
+    let rc = kconfig::load_cascade(
+        "plasmashellrc",
+        plasmashellrc_path,
+        config_dirs,
+        &mut layout.diagnostics,
+    )?;
+
+    let mut views: BTreeMap<u32, PanelGeometry> = BTreeMap::new();
+    for (path, kv) in rc.effective.iter_sections() {
+        if path.len() < 2 || path[0] != "PlasmaViews" {
+            continue;
+        }
+        let Some(id) = path[1]
+            .strip_prefix("Panel ")
+            .and_then(|n| n.trim().parse::<u32>().ok())
+        else {
+            continue;
+        };
+        let geom = views.entry(id).or_default();
+        match path.get(2).map(String::as_str) {
+            None => {
+                read_view_settings(geom, kv);
+                read_size(&mut geom.defaults, kv);
+            }
+            Some("Defaults") if path.len() == 3 => {
+                read_view_settings(geom, kv);
+                read_size(&mut geom.defaults, kv);
+            }
+            Some(screen) if path.len() == 3 => {
+                // Plasma 5 kept the alignment next to the sizes.
+                read_view_settings(geom, kv);
+                read_size(geom.screens.entry(screen.to_string()).or_default(), kv);
+            }
+            _ => {}
+        }
+    }
+
+    if let Some(connectors) = rc.effective.group(&["ScreenConnectors"]) {
+        for (id, connector) in connectors {
+            if let Ok(id) = id.trim().parse::<u32>() {
+                layout.screens.connectors.insert(id, connector.clone());
+            }
+        }
+    }
+
+    for c in layout.containments.iter_mut().filter(|c| c.is_panel) {
+        c.geometry = views.remove(&c.id).filter(|g| !g.is_empty());
+    }
+    Ok(())
+}
+
+fn number<T: std::str::FromStr>(kv: &BTreeMap<String, String>, key: &str) -> Option<T> {
+    kv.get(key).and_then(|v| v.trim().parse().ok())
+}
+
+fn read_view_settings(geom: &mut PanelGeometry, kv: &BTreeMap<String, String>) {
+    if let Some(a) = number(kv, "alignment").and_then(PanelAlignment::from_qt) {
+        geom.alignment = Some(a);
+    }
+    if let Some(h) = number(kv, "panelVisibility").and_then(PanelHiding::from_config) {
+        geom.hiding = Some(h);
+    }
+    if let Some(m) = number(kv, "panelLengthMode").and_then(PanelLengthMode::from_config) {
+        geom.length_mode = Some(m);
+    }
+    if let Some(f) = kv.get("floating") {
+        geom.floating = Some(matches!(f.trim(), "1" | "true"));
+    }
+}
+
+fn read_size(size: &mut PanelSize, kv: &BTreeMap<String, String>) {
+    size.thickness = number(kv, "thickness").or(size.thickness);
+    size.length = number(kv, "length").or(size.length);
+    size.min_length = number(kv, "minLength").or(size.min_length);
+    size.max_length = number(kv, "maxLength").or(size.max_length);
+    size.offset = number(kv, "offset").or(size.offset);
+}
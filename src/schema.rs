@@ -0,0 +1,195 @@
+use anyhow::{bail, Context, Result};
+use serde_json::{Map, Value};
+
+use crate::kconfig::{self, Escape};
+use crate::model::{Layout, SCHEMA_VERSION};
+use crate::parser;
+
+/// JSON Schema of the current layout.json format, generated from the model
+/// types; their doc comments become the descriptions.
+pub fn json_schema() -> Value {
+    let mut schema = serde_json::to_value(schemars::schema_for!(Layout)).unwrap();
+    if let Some(root) = schema.as_object_mut() {
+        root.insert(
+            "$comment".to_string(),
+            format!("layout.json schema_version {SCHEMA_VERSION}").into(),
+        );
+    }
+    schema
+}
+
+/// Reads a layout.json written by any version of this tool, upgrading
+/// older formats to the current [`Layout`].
+///
+/// Files without `schema_version` are version 0: what `scan` printed before
+/// the field existed.
+pub fn load_layout_json(text: &str, source: &str) -> Result<Layout> {
+    let mut value: Value =
+        serde_json::from_str(text).with_context(|| format!("parsing {source}"))?;
+    let Some(root) = value.as_object_mut() else {
+        bail!("{source}: expected a JSON object");
+    };
+    let version = match root.get("schema_version") {
+        None => 0,
+        Some(v) => v
+            .as_u64()
+            .and_then(|v| u32::try_from(v).ok())
+            .with_context(|| format!("{source}: invalid schema_version {v}"))?,
+    };
+    if version > SCHEMA_VERSION {
+        bail!(
+            "{source}: schema_version {version} is newer than this tool supports ({SCHEMA_VERSION}); update kdesktop-copycat"
+        );
+    }
+
+    if version < 1 {
+        upgrade_v0(root);
+    }
+    root.insert("schema_version".to_string(), SCHEMA_VERSION.into());
+
+    let mut layout: Layout =
+        serde_json::from_value(value).with_context(|| format!("reading {source}"))?;
+    if version < 1 {
+        parser::link_systrays(&mut layout.containments);
+    }
+    Ok(layout)
+}
+
+/// Version 0 kept keys and values exactly as written in the file: escapes
+/// undecoded, and `Name[de]` or `key[$i]` as separate keys. Containment
+/// enums were only available as raw `meta` values.
+fn upgrade_v0(root: &mut Map<String, Value>) {
+    let Some(Value::Array(containments)) = root.get_mut("containments") else {
+        return;
+    };
+    for c in containments.iter_mut().filter_map(Value::as_object_mut) {
+        upgrade_v0_meta(c);
+        if let Some(meta) = c.get("meta").and_then(Value::as_object).cloned() {
+            for (key, field) in [
+                ("location", "location"),
+                ("formfactor", "form_factor"),
+                ("immutability", "immutability"),
+            ] {
+                if let Some(v) = meta.get(key) {
+                    c.entry(field).or_insert(v.clone());
+                }
+            }
+        }
+
+        let Some(Value::Array(applets)) = c.get_mut("applets") else {
+            continue;
+        };
+        for a in applets.iter_mut().filter_map(Value::as_object_mut) {
+            upgrade_v0_meta(a);
+            let Some(Value::Object(config)) = a.get_mut("config") else {
+                continue;
+            };
+            let mut localized = Map::new();
+            let mut markers = Map::new();
+            for (group, kv) in config.iter_mut() {
+                let Value::Object(kv) = kv else {
+                    continue;
+                };
+                let (loc, mark) = split_v0_keys(kv);
+                if !loc.is_empty() {
+                    localized.insert(group.clone(), loc.into());
+                }
+                if !mark.is_empty() {
+                    markers.insert(group.clone(), mark.into());
+                }
+            }
+            if !localized.is_empty() {
+                a.insert("config_localized".to_string(), localized.into());
+            }
+            if !markers.is_empty() {
+                a.insert("config_markers".to_string(), markers.into());
+            }
+        }
+    }
+}
+
+/// Upgrades `obj.meta`, moving localized keys and markers into
+/// `obj.localized` and `obj.markers`.
+fn upgrade_v0_meta(obj: &mut Map<String, Value>) {
+    let Some(Value::Object(meta)) = obj.get_mut("meta") else {
+        return;
+    };
+    let (loc, mark) = split_v0_keys(meta);
+    if !loc.is_empty() {
+        obj.insert("localized".to_string(), loc.into());
+    }
+    if !mark.is_empty() {
+        obj.insert("markers".to_string(), mark.into());
+    }
+}
+
+/// Decodes the values of `kv` in place and takes out `key[locale]` entries
+/// and `[$i]`/`[$e]`/`[$d]` suffixes. Returns the localized values and the
+/// markers found, in their current JSON shape.
+fn split_v0_keys(kv: &mut Map<String, Value>) -> (Map<String, Value>, Map<String, Value>) {
+    let mut plain = Map::new();
+    let mut localized: Map<String, Value> = Map::new();
+    let mut marker_keys: [(&str, Vec<String>); 3] = [
+        ("immutable_keys", Vec::new()),
+        ("expanded_keys", Vec::new()),
+        ("deleted_keys", Vec::new()),
+    ];
+    let mut raw_values = Map::new();
+
+    for (raw_key, v) in std::mem::take(kv) {
+        let (value, raw) = match v {
+            Value::String(s) => (Value::String(kconfig::unescape(&s)), Some(s)),
+            other => (other, None),
+        };
+        let (key, suffixes) = match raw_key.find('[') {
+            Some(i) => (raw_key[..i].to_string(), &raw_key[i..]),
+            None => (raw_key.clone(), ""),
+        };
+        let mut locale = None;
+        for part in suffixes
+            .split(']')
+            .filter_map(|p| p.strip_prefix('['))
+            .filter(|p| !p.is_empty())
+        {
+            match part.strip_prefix('$') {
+                Some(flags) => {
+                    for (flag, (_, keys)) in ['i', 'e', 'd'].into_iter().zip(marker_keys.iter_mut())
+                    {
+                        if flags.contains(flag) {
+                            keys.push(key.clone());
+                        }
+                    }
+                }
+                None => locale = Some(part.to_string()),
+            }
+        }
+        match locale {
+            Some(l) => {
+                if let Value::Object(m) = localized.entry(key).or_insert_with(|| Map::new().into())
+                {
+                    m.insert(l, value);
+                }
+            }
+            None => {
+                if let (Some(raw), Value::String(decoded)) = (raw, &value) {
+                    if raw != kconfig::escape(decoded, Escape::Value) {
+                        raw_values.insert(key.clone(), raw.into());
+                    }
+                }
+                plain.insert(key, value);
+            }
+        }
+    }
+    *kv = plain;
+
+    let mut markers = Map::new();
+    for (field, keys) in marker_keys {
+        if !keys.is_empty() {
+            markers.insert(field.to_string(), keys.into());
+        }
+    }
+    if !raw_values.is_empty() {
+        markers.insert("raw_values".to_string(), raw_values.into());
+    }
+    (localized, markers)
+}
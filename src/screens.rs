@@ -0,0 +1,80 @@
+use anyhow::Result;
+use serde_json::Value;
+use std::fs;
+use std::path::Path;
+use std::time::SystemTime;
+
+use crate::model::{Layout, Output};
+
+/// Reads the monitor setup kscreen saved most recently under `dir`
+/// (`$XDG_DATA_HOME/kscreen`) into `layout.screens.outputs`.
+///
+/// kscreen keeps one JSON file per set of connected monitors, named by a
+/// hash of that set; the newest one is the setup in use. Files that are not
+/// kscreen output lists are skipped, and a missing directory is not an error.
+pub fn attach_kscreen_outputs(layout: &mut Layout, dir: &Path) -> Result<()> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Ok(());
+    };
+    let mut newest: Option<(SystemTime, std::path::PathBuf, Vec<Output>)> = None;
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if !path.is_file() {
+            continue;
+        }
+        let Ok(mtime) = entry.metadata().and_then(|m| m.modified()) else {
+            continue;
+        };
+        if newest.as_ref().is_some_and(|(t, _, _)| *t >= mtime) {
+            continue;
+        }
+        let Some(outputs) = fs::read_to_string(&path)
+            .ok()
+            .and_then(|raw| serde_json::from_str::<Value>(&raw).ok())
+            .and_then(|v| parse_outputs(&v))
+        else {
+            continue;
+        };
+        newest = Some((mtime, path, outputs));
+    }
+
+    if let Some((_, path, outputs)) = newest {
+        layout.screens.kscreen_config = Some(path.display().to_string());
+        layout.screens.outputs = outputs;
+    }
+    Ok(())
+}
+
+/// A kscreen config is a JSON array of outputs; `None` for anything else.
+fn parse_outputs(v: &Value) -> Option<Vec<Output>> {
+    let mut out = Vec::new();
+    for o in v.as_array()? {
+        let meta = o.get("metadata")?;
+        let connector = meta.get("name")?.as_str()?.to_string();
+        let int = |v: Option<&Value>| v.and_then(Value::as_i64).unwrap_or(0);
+        let pos = o.get("pos");
+        let size = o.get("mode").and_then(|m| m.get("size"));
+        out.push(Output {
+            connector,
+            name: meta
+                .get("fullname")
+                .and_then(Value::as_str)
+                .filter(|s| !s.is_empty())
+                .map(str::to_string),
+            enabled: o.get("enabled").and_then(Value::as_bool).unwrap_or(false),
+            // Plasma 5 wrote `primary`, Plasma 6 writes `priority` (1 = primary).
+            primary: o.get("primary").and_then(Value::as_bool).unwrap_or(false)
+                || o.get("priority").and_then(Value::as_i64) == Some(1),
+            x: int(pos.and_then(|p| p.get("x"))) as i32,
+            y: int(pos.and_then(|p| p.get("y"))) as i32,
+            width: int(size.and_then(|s| s.get("width"))) as u32,
+            height: int(size.and_then(|s| s.get("height"))) as u32,
+            refresh: o
+                .get("mode")
+                .and_then(|m| m.get("refresh"))
+                .and_then(Value::as_f64),
+            scale: o.get("scale").and_then(Value::as_f64),
+        });
+    }
+    Some(out)
+}
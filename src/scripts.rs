@@ -1,14 +1,24 @@
-use crate::model::Layout;
+use std::collections::BTreeMap;
 
+use crate::kconfig::{escape, written_value, Escape};
+use crate::kwin::DECORATION;
+use crate::model::{
+    Applet, Containment, KWinScan, Layout, Localized, Location, Markers, PanelGeometry, Wallpaper,
+};
+use crate::parser::{local_path, split_list_raw, APPLETSRC, SYSTRAY_APPLET, SYSTRAY_CONTAINMENT};
+
+/// `scripts/restore-snapshot.sh`: puts the bundled appletsrc back in place
+/// (keeping a backup) and restarts plasmashell.
 pub fn restore_snapshot_sh() -> String {
     r#"#!/usr/bin/env bash
 set -euo pipefail
 BUNDLE_DIR="$(cd "$(dirname "${BASH_SOURCE[0]}")/.." && pwd)"
 SRC="$BUNDLE_DIR/snapshot/plasma-org.kde.plasma.desktop-appletsrc"
-DST="$HOME/.config/plasma-org.kde.plasma.desktop-appletsrc"
+CONFIG_HOME="${XDG_CONFIG_HOME:-$HOME/.config}"
+DST="$CONFIG_HOME/plasma-org.kde.plasma.desktop-appletsrc"
 
 [[ -f "$SRC" ]] || { echo "Snapshot missing: $SRC"; exit 1; }
-mkdir -p "$HOME/.config"
+mkdir -p "$CONFIG_HOME"
 [[ ! -f "$DST" ]] || cp -a "$DST" "$DST.bak.$(date -u +%Y%m%dT%H%M%SZ)"
 cp -a "$SRC" "$DST"
 
@@ -23,6 +33,8 @@ echo "Snapshot restored."
     .to_string()
 }
 
+/// `scripts/restore-portable.sh`: installs bundled plasmoids and wallpapers,
+/// creates missing activities and runs restore-layout.js in plasmashell.
 pub fn restore_portable_sh() -> String {
     r#"#!/usr/bin/env bash
 set -euo pipefail
@@ -30,6 +42,22 @@ BUNDLE_DIR="$(cd "$(dirname "${BASH_SOURCE[0]}")/.." && pwd)"
 PLASMOIDS_DIR="$BUNDLE_DIR/plasmoids"
 JS="$BUNDLE_DIR/scripts/restore-layout.js"
 
+# --map-screen FROM=TO: put what was on output FROM onto output TO. Parsed
+# before anything is installed, so a bad argument changes nothing.
+# Names end up in the JS prelude, so only connector-like names are taken.
+EXTRA_MAP=""
+while [[ $# -gt 0 ]]; do
+  case "$1" in
+    --map-screen) [[ $# -ge 2 ]] || { echo "--map-screen needs FROM=TO"; exit 1; }; m="$2"; shift 2 ;;
+    --map-screen=*) m="${1#*=}"; shift ;;
+    *) echo "Unknown option: $1"; exit 1 ;;
+  esac
+  from="${m%%=*}" to="${m#*=}"
+  [[ "$m" == *=* && "$from" =~ ^[A-Za-z0-9_-]+$ && "$to" =~ ^[A-Za-z0-9_-]+$ ]] ||
+    { echo "--map-screen needs FROM=TO with output names like DP-1, got: $m"; exit 1; }
+  EXTRA_MAP+="\"$from\":\"$to\","
+done
+
 if [[ -d "$PLASMOIDS_DIR" ]]; then
   shopt -s nullglob
   for d in "$PLASMOIDS_DIR"/*; do
@@ -39,18 +67,94 @@ if [[ -d "$PLASMOIDS_DIR" ]]; then
   done
 fi
 
+CONFIG_HOME="${XDG_CONFIG_HOME:-$HOME/.config}"
+
+WALLPAPERS_DIR="$BUNDLE_DIR/wallpapers"
+if [[ -d "$WALLPAPERS_DIR" ]]; then
+  DST_WALLPAPERS="${XDG_DATA_HOME:-$HOME/.local/share}/wallpapers/kdesktop-copycat"
+  echo "Installing wallpapers into $DST_WALLPAPERS"
+  mkdir -p "$DST_WALLPAPERS"
+  cp -a "$WALLPAPERS_DIR/." "$DST_WALLPAPERS/"
+fi
+
 [[ -f "$JS" ]] || { echo "Missing JS: $JS"; exit 1; }
 
 QDBUS="$(command -v qdbus6 || command -v qdbus || true)"
 [[ -n "$QDBUS" ]] || { echo "Need qdbus6 (qt6-tools)."; exit 1; }
 
+# Activities missing on this machine, created up front so they get their icons
+ACTIVITIES="$BUNDLE_DIR/activities.tsv"
+am() { "$QDBUS" org.kde.ActivityManager /ActivityManager/Activities "org.kde.ActivityManager.Activities.$1" "${@:2}"; }
+if [[ -f "$ACTIVITIES" ]] && am ListActivities >/dev/null 2>&1; then
+  EXISTING="$(for id in $(am ListActivities); do am ActivityName "$id"; done)"
+  while IFS=$'\t' read -r name icon description; do
+    [[ -n "$name" ]] || continue
+    grep -qxF -- "$name" <<<"$EXISTING" && continue
+    echo "Creating activity: $name"
+    id="$(am AddActivity "$name")"
+    [[ -z "$icon" ]] || am SetActivityIcon "$id" "$icon" >/dev/null
+    [[ -z "$description" ]] || am SetActivityDescription "$id" "$description" >/dev/null
+  done < "$ACTIVITIES"
+fi
+
+# Screen ids of this machine's outputs, from plasmashellrc [ScreenConnectors]
+TARGET=""
+if [[ -f "$CONFIG_HOME/plasmashellrc" ]]; then
+  TARGET="$(awk -F= '
+    /^\[/ { in_group = ($0 == "[ScreenConnectors]") ; next }
+    in_group && $1 ~ /^[0-9]+$/ && $2 ~ /^[A-Za-z0-9_-]+$/ { printf "\"%s\":%s,", $2, $1 }
+  ' "$CONFIG_HOME/plasmashellrc")"
+fi
+PRELUDE="var TARGET_SCREENS = {${TARGET%,}}; var EXTRA_SCREEN_MAP = {${EXTRA_MAP%,}};"
+
 echo "Applying layout via evaluateScript..."
-"$QDBUS" org.kde.plasmashell /PlasmaShell org.kde.PlasmaShell.evaluateScript "$(cat "$JS")"
+OUT="$("$QDBUS" org.kde.plasmashell /PlasmaShell org.kde.PlasmaShell.evaluateScript "$PRELUDE
+$(cat "$JS")")"
+grep -v '^@' <<<"$OUT" || true
+
+# `@set<TAB>group/path<TAB>key<TAB>line` from the script: replace `key` in
+# that group with `line`. plasmashell saves appletsrc when it quits, so this
+# runs while it is stopped.
+APPLETSRC="$CONFIG_HOME/plasma-org.kde.plasma.desktop-appletsrc"
+patch_appletsrc() {
+  local sets
+  sets="$(grep '^@set' <<<"$OUT" || true)"
+  [[ -n "$sets" && -f "$APPLETSRC" ]] || return 0
+  awk -F'\t' '
+    function flush(   n, i, ks) {
+      n = (cur in keys) ? split(keys[cur], ks, SUBSEP) : 0
+      for (i = 2; i <= n; i++) if (!((cur, ks[i]) in done)) { print want[cur, ks[i]]; done[cur, ks[i]] = 1 }
+      printf "%s", blank; blank = ""
+    }
+    NR == FNR {
+      h = ""; n = split($2, p, "/"); for (i = 1; i <= n; i++) h = h "[" p[i] "]"
+      if (!((h, $3) in want)) keys[h] = keys[h] SUBSEP $3
+      want[h, $3] = $4; next
+    }
+    /^[ \t]*$/ { blank = blank $0 "\n"; next }
+    /^[ \t]*\[/ { flush(); cur = $0; gsub(/^[ \t]+|[ \t]+$/, "", cur); seen[cur] = 1; print; next }
+    {
+      printf "%s", blank; blank = ""
+      k = $0; sub(/=.*/, "", k); gsub(/^[ \t]+|[ \t]+$/, "", k); sub(/(\[\$[a-z]*\])+$/, "", k)
+      if ((cur, k) in want) { print want[cur, k]; done[cur, k] = 1; next }
+      print
+    }
+    END {
+      tail = blank; blank = ""; flush()
+      for (h in keys) if (!(h in seen)) { printf "%s", (tail != "" ? tail : "\n"); tail = ""; print h; cur = h; flush() }
+      printf "%s", tail
+    }
+  ' <(printf '%s\n' "$sets") "$APPLETSRC" > "$APPLETSRC.tmp"
+  mv "$APPLETSRC.tmp" "$APPLETSRC"
+}
 
 if systemctl --user status plasma-plasmashell.service >/dev/null 2>&1; then
-  systemctl --user restart plasma-plasmashell.service
+  systemctl --user stop plasma-plasmashell.service
+  patch_appletsrc
+  systemctl --user start plasma-plasmashell.service
 else
   kquitapp6 plasmashell >/dev/null 2>&1 || true
+  patch_appletsrc
   (plasmashell --replace >/dev/null 2>&1 & disown) || true
 fi
 echo "Portable restore complete."
@@ -58,7 +162,314 @@ echo "Portable restore complete."
     .to_string()
 }
 
-pub fn restore_layout_js(layout: &Layout) -> String {
+/// `scripts/restore-kwin-rules.sh`: merges `kwin/kwinrulesrc` into the
+/// user's kwinrulesrc. Rules with the same group name are replaced, the rest
+/// of the machine's rules stay, and `[General] rules=` lists both.
+pub fn restore_kwin_rules_sh() -> String {
+    r#"#!/usr/bin/env bash
+set -euo pipefail
+BUNDLE_DIR="$(cd "$(dirname "${BASH_SOURCE[0]}")/.." && pwd)"
+SRC="$BUNDLE_DIR/kwin/kwinrulesrc"
+CONFIG_HOME="${XDG_CONFIG_HOME:-$HOME/.config}"
+DST="$CONFIG_HOME/kwinrulesrc"
+
+[[ -f "$SRC" ]] || { echo "Window rules missing: $SRC"; exit 1; }
+mkdir -p "$CONFIG_HOME"
+touch "$DST"
+cp -a "$DST" "$DST.bak.$(date -u +%Y%m%dT%H%M%SZ)"
+
+# general FILE KEY: a [General] value
+general() {
+  awk -v key="$2" '
+    /^\[/ { in_group = ($0 == "[General]"); next }
+    in_group && index($0, key "=") == 1 { value = substr($0, length(key) + 2) }
+    END { print value }
+  ' "$1"
+}
+
+NEW="$(general "$SRC" rules)"
+OLD="$(general "$DST" rules)"
+if [[ -z "$OLD" ]]; then
+  # Before Plasma 5.20 the rules were [1] .. [count]
+  n="$(general "$DST" count)"
+  [[ "${n:-0}" =~ ^[0-9]+$ ]] && (( n > 0 )) && OLD="$(seq -s, 1 "$n")"
+fi
+
+# items LIST: the entries of a KConfig list, one per line, still escaped;
+# `\,` is a comma inside an entry, not a separator
+items() {
+  awk '{
+    item = ""
+    for (i = 1; i <= length($0); i++) {
+      c = substr($0, i, 1)
+      if (c == "\\") item = item c substr($0, ++i, 1)
+      else if (c == ",") { if (item != "") print item; item = "" }
+      else item = item c
+    }
+    if (item != "") print item
+  }' <<<"$1"
+}
+
+mapfile -t ids < <(items "$NEW")
+mapfile -t all < <(items "$OLD")
+declare -A known=()
+for id in "${all[@]}"; do known["$id"]=1; done
+for id in "${ids[@]}"; do
+  [[ -n "${known["$id"]:-}" ]] || { all+=("$id"); known["$id"]=1; }
+done
+RULES="$(IFS=,; echo "${all[*]}")"
+COUNT="${#all[@]}"
+
+TMP="$(mktemp "$DST.XXXXXX")"
+{
+  # The machine's file without the imported groups and the old index
+  # Through the environment: awk -v would eat the backslash of `\,`
+  IDS="$(printf '%s\n' "${ids[@]}")" RULES="$RULES" awk -v count="$COUNT" '
+    # Blank lines are held back so trailing ones do not pile up on every run
+    function out(line) { for (; blank > 0; blank--) print ""; print line }
+    BEGIN {
+      rules = ENVIRON["RULES"]
+      # Group names carry the comma of an id unescaped
+      n = split(ENVIRON["IDS"], a, "\n")
+      for (i = 1; i <= n; i++) { id = a[i]; gsub(/\\,/, ",", id); drop["[" id "]"] = 1 }
+    }
+    /^\[/ {
+      skip = ($0 in drop); in_general = ($0 == "[General]")
+      if (in_general) { out($0); print "count=" count; print "rules=" rules; seen = 1; next }
+    }
+    skip { next }
+    in_general && /^(count|rules)=/ { next }
+    /^[[:space:]]*$/ { blank++; next }
+    { out($0) }
+    END { if (!seen) { if (NR) print ""; print "[General]"; print "count=" count; print "rules=" rules } }
+  ' "$DST"
+  echo
+  # The bundled rules, without their own index
+  awk '/^\[/ { in_general = ($0 == "[General]") } !in_general { print }' "$SRC"
+} > "$TMP"
+mv "$TMP" "$DST"
+
+QDBUS="$(command -v qdbus6 || command -v qdbus || true)"
+if [[ -n "$QDBUS" ]]; then
+  "$QDBUS" org.kde.KWin /KWin reconfigure >/dev/null 2>&1 || true
+fi
+echo "Window rules merged: ${#ids[@]} from the bundle, $COUNT in total."
+"#
+    .to_string()
+}
+
+/// KWin packages copied into the bundle's `kwin/`, by the name they are
+/// stored under.
+#[derive(Debug, Clone, Default)]
+pub struct KWinPackages {
+    /// Script ids under `kwin/scripts/`
+    pub scripts: Vec<String>,
+
+    /// Effect ids under `kwin/effects/`
+    pub effects: Vec<String>,
+
+    /// Aurorae SVG theme under `kwin/aurorae/`
+    pub aurorae: Option<String>,
+
+    /// QML decoration package under `kwin/decorations/`
+    pub decoration: Option<String>,
+}
+
+impl KWinPackages {
+    pub fn len(&self) -> usize {
+        self.scripts.len()
+            + self.effects.len()
+            + usize::from(self.aurorae.is_some())
+            + usize::from(self.decoration.is_some())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// `scripts/restore-kwin.sh`: installs the bundled KWin scripts, effects and
+/// decoration, writes the captured kwinrc settings with `kwriteconfig6` and
+/// has KWin reload them. Virtual desktops only apply once KWin restarts,
+/// which the script says when it changes them. `--dry-run` prints each key
+/// it would change, with its current value, and touches nothing.
+///
+/// Values that came from a system kwinrc (see [`KWinScan::provenance`]) are
+/// defaults of the source machine and are left alone.
+pub fn restore_kwin_sh(kwin: &KWinScan, packages: &KWinPackages) -> String {
+    let mut sh = String::from(
+        r#"#!/usr/bin/env bash
+set -euo pipefail
+BUNDLE_DIR="$(cd "$(dirname "${BASH_SOURCE[0]}")/.." && pwd)"
+KWIN_DIR="$BUNDLE_DIR/kwin"
+DATA_HOME="${XDG_DATA_HOME:-$HOME/.local/share}"
+
+DRY_RUN=0
+while [[ $# -gt 0 ]]; do
+  case "$1" in
+    -n|--dry-run) DRY_RUN=1; shift ;;
+    *) echo "Unknown option: $1"; exit 1 ;;
+  esac
+done
+
+# install_package TYPE DIR: upgrades the KPackage in DIR, or installs it
+install_package() {
+  if (( DRY_RUN )); then echo "Would install $1: $(basename "$2")"; return; fi
+  echo "Installing $1: $(basename "$2")"
+  kpackagetool6 -t "$1" -u "$2" >/dev/null 2>&1 || kpackagetool6 -t "$1" -i "$2"
+}
+
+# install_aurorae NAME: copies an Aurorae SVG theme into place
+install_aurorae() {
+  if (( DRY_RUN )); then echo "Would install Aurorae theme: $1"; return; fi
+  echo "Installing Aurorae theme: $1"
+  mkdir -p "$DATA_HOME/aurorae/themes"
+  cp -a "$KWIN_DIR/aurorae/$1" "$DATA_HOME/aurorae/themes/"
+}
+
+# kwinrc GROUP KEY VALUE: sets a kwinrc key unless it already has VALUE
+CHANGED=0
+DESKTOPS_CHANGED=0
+kwinrc() {
+  local current
+  current="$(kreadconfig6 --file kwinrc --group "$1" --key "$2" 2>/dev/null || true)"
+  [[ "$current" != "$3" ]] || return 0
+  CHANGED=$((CHANGED + 1))
+  [[ "$1" != Desktops ]] || DESKTOPS_CHANGED=1
+  if (( DRY_RUN )); then
+    echo "kwinrc [$1] $2: ${current:-(unset)} -> $3"
+  else
+    kwriteconfig6 --file kwinrc --group "$1" --key "$2" "$3"
+  fi
+}
+"#,
+    );
+
+    sh.push('\n');
+    for (kind, dir, ids) in [
+        ("KWin/Script", "scripts", &packages.scripts),
+        ("KWin/Effect", "effects", &packages.effects),
+    ] {
+        for id in ids {
+            sh.push_str(&format!(
+                "install_package {kind} \"$KWIN_DIR/{dir}/\"{}\n",
+                sh_quote(id)
+            ));
+        }
+    }
+    if let Some(theme) = &packages.aurorae {
+        sh.push_str(&format!("install_aurorae {}\n", sh_quote(theme)));
+    }
+    if let Some(id) = &packages.decoration {
+        sh.push_str(&format!(
+            "install_package KWin/Decoration \"$KWIN_DIR/decorations/\"{}\n",
+            sh_quote(id)
+        ));
+    }
+
+    // Plugins are switched on only after their packages are installed, so
+    // KWin never tries to load a missing one.
+    let mut set = |group: &str, key: &str, value: &str| {
+        if !kwin.provenance.contains_key(&format!("{group}/{key}")) {
+            sh.push_str(&format!(
+                "kwinrc {} {} {}\n",
+                sh_quote(group),
+                sh_quote(key),
+                sh_quote(value)
+            ));
+        }
+    };
+    for p in &kwin.plugins {
+        set(
+            "Plugins",
+            &format!("{}Enabled", p.id),
+            &p.enabled.to_string(),
+        );
+    }
+    let s = &kwin.summary;
+    for (group, kv) in [
+        ("TabBox", &s.task_switcher),
+        ("TabBoxAlternative", &s.task_switcher_alternative),
+        (DECORATION, &s.decoration),
+    ] {
+        for (key, value) in kv {
+            set(group, key, value);
+        }
+    }
+    if let Some(vd) = &s.virtual_desktops {
+        set("Desktops", "Number", &vd.desktops.len().to_string());
+        if let Some(rows) = vd.rows {
+            set("Desktops", "Rows", &rows.to_string());
+        }
+        for (i, d) in vd.desktops.iter().enumerate() {
+            let n = i + 1;
+            if let Some(id) = &d.id {
+                set("Desktops", &format!("Id_{n}"), id);
+            }
+            if let Some(name) = &d.name {
+                set("Desktops", &format!("Name_{n}"), name);
+            }
+        }
+    }
+
+    sh.push_str(
+        r#"
+if (( DRY_RUN )); then
+  echo "Dry run: $CHANGED kwinrc key(s) would change."
+  exit 0
+fi
+QDBUS="$(command -v qdbus6 || command -v qdbus || true)"
+if [[ -n "$QDBUS" ]]; then
+  "$QDBUS" org.kde.KWin /KWin reconfigure >/dev/null 2>&1 || true
+fi
+# KWin reads [Desktops] only when it starts. Creating desktops over D-Bus
+# would give them new ids, and window rules refer to the bundled ones.
+if (( DESKTOPS_CHANGED )); then
+  echo "Virtual desktops changed: restart KWin (log out and back in) to apply them."
+fi
+echo "KWin restore complete: $CHANGED kwinrc key(s) changed."
+"#,
+    );
+    sh
+}
+
+/// Quotes `s` as a single shell word.
+fn sh_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', r"'\''"))
+}
+
+/// `activities.tsv` for restore-portable.sh: one `name<TAB>icon<TAB>description`
+/// line per activity.
+pub fn activities_tsv(layout: &Layout) -> String {
+    let clean = |s: &str| s.replace(['\t', '\n', '\r'], " ");
+    let mut out = String::new();
+    for a in &layout.activities {
+        out.push_str(&format!(
+            "{}\t{}\t{}\n",
+            clean(&a.name),
+            clean(a.icon.as_deref().unwrap_or("")),
+            clean(a.description.as_deref().unwrap_or(""))
+        ));
+    }
+    out
+}
+
+/// Choices made at export time that end up in restore-layout.js.
+#[derive(Debug, Clone, Default)]
+pub struct RestoreOpts {
+    /// `--map-screen`: source connector → target connector. Panels go to
+    /// whichever screen has the target connector on the restoring machine.
+    pub screen_map: BTreeMap<String, String>,
+
+    /// Wallpaper path as written in the config → file name under the
+    /// bundle's `wallpapers/`, which restore-portable.sh installs into
+    /// `$XDG_DATA_HOME/wallpapers/kdesktop-copycat`
+    pub wallpapers: BTreeMap<String, String>,
+}
+
+/// `scripts/restore-layout.js`: Plasma scripting code that adds the panels,
+/// widgets and desktop settings of `layout` to the running shell.
+pub fn restore_layout_js(layout: &Layout, opts: &RestoreOpts) -> String {
     let mut js = String::new();
     js.push_str(
         r#"// Auto-generated by plasma-layout-tui
@@ -70,14 +481,108 @@ function writeConfigTree(widget, groupPath, kv) {
   for (var k in kv) { widget.writeConfig(k, String(kv[k])); }
 }
 
+// Sizes are kept per screen size; pick the one for the panel's screen.
+function applyPanelGeometry(panel, screen, view, defaults, screens) {
+  if (view.lengthMode) panel.lengthMode = view.lengthMode;
+  if (view.alignment) panel.alignment = view.alignment;
+  if (view.hiding) panel.hiding = view.hiding;
+  if (view.floating !== undefined) panel.floating = view.floating;
+  var rect = screenGeometry(screen);
+  var size = screens["Horizontal" + rect.width] || screens["Vertical" + rect.height] || {};
+  var pick = function (k) { return size[k] !== undefined ? size[k] : defaults[k]; };
+  if (pick("thickness") !== undefined) panel.height = pick("thickness");
+  if (pick("min_length") !== undefined) panel.minimumLength = pick("min_length");
+  if (pick("max_length") !== undefined) panel.maximumLength = pick("max_length");
+  if (pick("length") !== undefined) panel.length = pick("length");
+  if (pick("offset") !== undefined) panel.offset = pick("offset");
+}
+
+// Groups no containment API reaches (e.g. [ActionPlugins]) go through ConfigFile.
+function writeConfigFile(file, groupPath, kv) {
+  var parts = groupPath.split("/");
+  var group = new ConfigFile(file, parts[0]);
+  for (var i = 1; i < parts.length; i++) { group = new ConfigFile(group, parts[i]); }
+  for (var k in kv) { group.writeEntry(k, String(kv[k])); }
+}
+
+// The tray creates applets for its enabled items itself; reuse those.
+function widgetIn(containment, plugin) {
+  var found = containment.widgets(plugin);
+  return found.length > 0 ? found[0] : containment.addWidget(plugin);
+}
+
+// The scripting API only writes plain values; restore-portable.sh puts
+// these lines into appletsrc once plasmashell has quit.
+function setAfterQuit(group, key, line) { print("@set\t" + group + "\t" + key + "\t" + line); }
+
 safePrint("Starting layout restore... (additive)");
 "#,
     );
 
+    // restore-portable.sh prepends TARGET_SCREENS (connector → screen id on
+    // this machine) and EXTRA_SCREEN_MAP (its own --map-screen options).
+    js.push_str(&format!(
+        r#"
+var WALLPAPER_DIR = userDataPath() + "/wallpapers/kdesktop-copycat";
+var SOURCE_SCREENS = {};
+var SCREEN_MAP = {};
+function targetScreen(source) {{
+  var conn = SOURCE_SCREENS[source];
+  if (conn === undefined) return source;
+  if (typeof EXTRA_SCREEN_MAP !== "undefined" && EXTRA_SCREEN_MAP[conn]) conn = EXTRA_SCREEN_MAP[conn];
+  else if (SCREEN_MAP[conn]) conn = SCREEN_MAP[conn];
+  if (typeof TARGET_SCREENS !== "undefined" && TARGET_SCREENS[conn] !== undefined) return TARGET_SCREENS[conn];
+  return source;
+}}
+"#,
+        serde_json::to_string(&layout.screens.connectors).unwrap(),
+        serde_json::to_string(&opts.screen_map).unwrap()
+    ));
+
+    // Activities are matched by name; restore-portable.sh creates missing
+    // ones (with their icons) before running this script.
+    let activity_names: BTreeMap<&str, &str> = layout
+        .activities
+        .iter()
+        .map(|a| (a.id.as_str(), a.name.as_str()))
+        .collect();
+    js.push_str(&format!(
+        r#"
+var SOURCE_ACTIVITIES = {};
+function targetActivity(source) {{
+  var name = SOURCE_ACTIVITIES[source];
+  if (name === undefined) return currentActivity();
+  var ids = activities();
+  for (var i = 0; i < ids.length; i++) {{
+    if (activityName(ids[i]) == name) return ids[i];
+  }}
+  safePrint("Creating activity " + name);
+  return createActivity(name);
+}}
+
+function desktopFor(activity, screen) {{
+  var ds = desktopsForActivity(activity);
+  for (var i = 0; i < ds.length; i++) {{
+    if (ds[i].screen == screen) return ds[i];
+  }}
+  return null;
+}}
+"#,
+        serde_json::to_string(&activity_names).unwrap()
+    ));
+
     for c in &layout.containments {
         if !c.is_panel {
             continue;
         }
+        if c.is_locked() {
+            // [$i] groups are managed by the system config; writing them would be ignored.
+            js.push_str(&format!(
+                "\nsafePrint(\"Skipping panel {cid}: locked with [$i]\");\n",
+                cid = c.id
+            ));
+            continue;
+        }
         js.push_str(&format!(
             r#"
 safePrint("Creating panel {cid}...");
@@ -85,8 +590,29 @@ var p_{cid} = new Panel();
 "#,
             cid = c.id
         ));
+        if let Some(loc) = c.location.and_then(Location::script_name) {
+            js.push_str(&format!("p_{}.location = \"{loc}\";\n", c.id));
+        }
+        // Panels cannot be moved from a script, and plasmashell saves the
+        // screen a panel is on when it quits. restore-portable.sh writes
+        // lastScreen after that, and plasmashell places the panel by it when
+        // it starts again.
+        let screen = match c.screen() {
+            Some(n) => {
+                js.push_str(&format!(
+                    "var s_{cid} = targetScreen({n});\nsetAfterQuit(\"Containments/\" + p_{cid}.id, \"lastScreen\", \"lastScreen=\" + s_{cid});\n",
+                    cid = c.id
+                ));
+                format!("s_{}", c.id)
+            }
+            None => format!("p_{}.screen", c.id),
+        };
+        if let Some(g) = &c.geometry {
+            push_panel_geometry(&mut js, &format!("p_{}", c.id), &screen, g);
+        }
+        push_containment_config(&mut js, &format!("p_{}", c.id), c);
 
-        for a in &c.applets {
+        for a in c.applets_in_order() {
             if let Some(pid) = &a.plugin {
                 js.push_str(&format!(
                     r#"
@@ -98,33 +624,664 @@ var w_{cid}_{aid} = p_{cid}.addWidget("{pid}");
                     pid = pid
                 ));
 
-                for (group, kv) in &a.config {
-                    let mut obj = String::from("{");
-                    let mut first = true;
-                    for (k, v) in kv {
-                        if !first {
-                            obj.push(',');
-                        }
-                        first = false;
-                        obj.push_str(&format!(
-                            "{}:{}",
-                            serde_json::to_string(k).unwrap(),
-                            serde_json::to_string(v).unwrap()
-                        ));
-                    }
-                    obj.push('}');
-                    js.push_str(&format!(
-                        "writeConfigTree(w_{cid}_{aid}, {g}, {obj});\n",
-                        cid = c.id,
-                        aid = a.id,
-                        g = serde_json::to_string(group).unwrap(),
-                        obj = obj
-                    ));
+                if let Some(tray) = a.child_containment.and_then(|id| layout.containment(id)) {
+                    push_systray(&mut js, &format!("w_{}_{}", c.id, a.id), tray);
                 }
+                let w = format!("w_{}_{}", c.id, a.id);
+                push_widget_config(&mut js, &format!("p_{}", c.id), &w, a);
+                push_widget_groups(&mut js, &format!("p_{}", c.id), &w, a);
             }
         }
     }
 
+    for c in &layout.containments {
+        let is_tray = c.parent_applet.is_some() || c.plugin.as_deref() == Some(SYSTRAY_CONTAINMENT);
+        if !c.is_panel && !is_tray && (c.wallpaper.is_some() || !c.config.is_empty()) {
+            push_desktop(&mut js, c, &opts.wallpapers);
+        }
+    }
+
+    if !layout.action_plugins.is_empty() {
+        js.push_str(
+            "
+safePrint(\"Restoring mouse actions...\");\n",
+        );
+        for (group, kv) in &layout.action_plugins {
+            js.push_str(&format!(
+                "writeConfigFile({}, {}, {});\n",
+                serde_json::to_string(APPLETSRC).unwrap(),
+                serde_json::to_string(&format!("ActionPlugins/{group}")).unwrap(),
+                serde_json::to_string(kv).unwrap()
+            ));
+        }
+    }
+
     js.push_str(r#"safePrint("Done.");"#);
     js
 }
+
+/// Sets the wallpaper and settings of the desktop standing in for `c`,
+/// pointing bundled files at their restored location.
+fn push_desktop(js: &mut String, c: &Containment, bundled: &BTreeMap<String, String>) {
+    if c.is_locked() {
+        js.push_str(&format!(
+            "\nsafePrint(\"Skipping desktop {}: locked with [$i]\");\n",
+            c.id
+        ));
+        return;
+    }
+    let var = format!("d_{}", c.id);
+    js.push_str(&format!(
+        r#"
+safePrint("Restoring desktop {cid}...");
+var {var} = {lookup};
+if ({var}) {{
+"#,
+        cid = c.id,
+        lookup = desktop_lookup_js(c),
+    ));
+    if let Some(wp) = &c.wallpaper {
+        push_wallpaper(js, &var, wp, bundled);
+    }
+    push_containment_config(js, &var, c);
+    js.push_str(&format!(
+        "}} else {{\n  safePrint(\"No matching desktop for {}, skipped\");\n}}\n",
+        c.id
+    ));
+}
+
+/// Sets wallpaper plugin and settings of the JS desktop `var`.
+fn push_wallpaper(js: &mut String, var: &str, wp: &Wallpaper, bundled: &BTreeMap<String, String>) {
+    js.push_str(&format!(
+        "{var}.wallpaperPlugin = {};\n",
+        serde_json::to_string(&wp.plugin).unwrap()
+    ));
+    for (group, kv) in &wp.config {
+        let mut path = vec!["Wallpaper".to_string(), wp.plugin.clone()];
+        path.extend(group.split('/').map(str::to_string));
+        js.push_str(&format!(
+            "{var}.currentConfigGroup = {};\n",
+            serde_json::to_string(&path).unwrap()
+        ));
+        let markers = wp.config_markers.get(group);
+        for (k, v) in kv {
+            // Bundled files are restored to a fixed place, so their paths
+            // no longer need expanding.
+            let value = match markers.and_then(|m| m.expand(k, v)) {
+                Some(expanded) if lists_bundled(&expanded, bundled) => {
+                    wallpaper_value_js(&expanded, bundled)
+                }
+                _ => wallpaper_value_js(v, bundled),
+            };
+            js.push_str(&format!(
+                "{var}.writeConfig({}, {value});\n",
+                serde_json::to_string(k).unwrap(),
+            ));
+        }
+        push_expanded_keys(
+            js,
+            &format!(
+                "\"Containments/\" + {var}.id + {}",
+                serde_json::to_string(&format!("/{}", path.join("/"))).unwrap()
+            ),
+            kv,
+            markers,
+            |k| {
+                markers
+                    .and_then(|m| m.expand(k, &kv[k]))
+                    .is_some_and(|e| lists_bundled(&e, bundled))
+            },
+        );
+    }
+}
+
+/// Replays the configuration groups of containment `c` (`[General]`,
+/// `[ConfigDialog]`, ...) onto the JS containment `var`.
+fn push_containment_config(js: &mut String, var: &str, c: &Containment) {
+    let is_tray = c.plugin.as_deref() == Some(SYSTRAY_CONTAINMENT);
+    for (group, kv) in &c.config {
+        let skip = |key: &str| {
+            c.is_config_key_locked(group, key)
+                // Widgets get new ids; they are added in order instead.
+                || (group == "General" && key == "AppletOrder")
+                // Written as lists by push_systray.
+                || (is_tray
+                    && group == "General"
+                    && matches!(
+                        key,
+                        "extraItems" | "knownItems" | "shownItems" | "hiddenItems"
+                    ))
+        };
+        let obj = config_object(kv, c.config_localized.get(group), skip);
+        if obj != "{}" {
+            js.push_str(&format!(
+                "writeConfigTree({var}, {g}, {obj});\n",
+                g = serde_json::to_string(group).unwrap(),
+            ));
+        }
+        push_expanded_keys(
+            js,
+            &format!(
+                "\"Containments/\" + {var}.id + {}",
+                serde_json::to_string(&format!("/{group}")).unwrap()
+            ),
+            kv,
+            c.config_markers.get(group),
+            skip,
+        );
+    }
+}
+
+/// JS expression for the restored desktop standing in for `c`: the one on
+/// its (remapped) screen in its activity, or in the current activity for
+/// desktops without one.
+fn desktop_lookup_js(c: &Containment) -> String {
+    let screen = c.screen().unwrap_or(0);
+    match c.activity() {
+        Some(id) => format!(
+            "desktopFor(targetActivity({}), targetScreen({screen}))",
+            serde_json::to_string(id).unwrap()
+        ),
+        None => format!("desktopForScreen(targetScreen({screen}))"),
+    }
+}
+
+/// JS expression for a wallpaper setting: a string literal, or, when the
+/// value lists bundled files, a concatenation placing them under
+/// `WALLPAPER_DIR`.
+fn wallpaper_value_js(value: &str, bundled: &BTreeMap<String, String>) -> String {
+    if !lists_bundled(value, bundled) {
+        return serde_json::to_string(value).unwrap();
+    }
+    let items = split_list_raw(value);
+    let mut parts = Vec::new();
+    for (n, item) in items.iter().enumerate() {
+        let sep = if n > 0 { "," } else { "" };
+        let trimmed = item.trim();
+        match local_path(trimmed).and_then(|p| bundled.get(&p)) {
+            Some(name) => {
+                let scheme = if trimmed.starts_with("file://") {
+                    "file://"
+                } else {
+                    ""
+                };
+                let slash = if trimmed.ends_with('/') { "/" } else { "" };
+                let prefix = format!("{sep}{scheme}");
+                if !prefix.is_empty() {
+                    parts.push(serde_json::to_string(&prefix).unwrap());
+                }
+                parts.push("WALLPAPER_DIR".to_string());
+                parts.push(serde_json::to_string(&format!("/{name}{slash}")).unwrap());
+            }
+            None => parts.push(
+                serde_json::to_string(&format!("{sep}{}", item.replace(',', "\\,"))).unwrap(),
+            ),
+        }
+    }
+    parts.join(" + ")
+}
+
+/// Whether the wallpaper setting `value` lists any bundled file.
+fn lists_bundled(value: &str, bundled: &BTreeMap<String, String>) -> bool {
+    split_list_raw(value)
+        .iter()
+        .any(|i| local_path(i.trim()).is_some_and(|p| bundled.contains_key(&p)))
+}
+
+/// Restores size, alignment and visibility of the JS panel `var`, sized
+/// for the JS screen expression `screen`.
+fn push_panel_geometry(js: &mut String, var: &str, screen: &str, g: &PanelGeometry) {
+    let mut view = serde_json::Map::new();
+    if let Some(m) = g.length_mode {
+        view.insert("lengthMode".into(), m.script_name().into());
+    }
+    if let Some(a) = g.alignment {
+        view.insert("alignment".into(), a.script_name().into());
+    }
+    if let Some(h) = g.hiding {
+        view.insert("hiding".into(), h.script_name().into());
+    }
+    if let Some(f) = g.floating {
+        view.insert("floating".into(), f.into());
+    }
+    js.push_str(&format!(
+        "applyPanelGeometry({var}, {screen}, {}, {}, {});\n",
+        serde_json::Value::Object(view),
+        serde_json::to_string(&g.defaults).unwrap(),
+        serde_json::to_string(&g.screens).unwrap()
+    ));
+}
+
+/// Fills the containment a freshly added system tray applet (`widget`)
+/// created for itself with the items and widgets of `tray`.
+fn push_systray(js: &mut String, widget: &str, tray: &Containment) {
+    if tray.is_locked() {
+        js.push_str(&format!(
+            "safePrint(\"  Skipping system tray {}: locked with [$i]\");\n",
+            tray.id
+        ));
+        return;
+    }
+    let var = format!("t_{}", tray.id);
+    js.push_str(&format!(
+        "var {var} = desktopById({widget}.readConfig(\"SystrayContainmentId\"));\n"
+    ));
+    js.push_str(&format!("if ({var}) {{\n"));
+    if let Some(items) = &tray.systray {
+        js.push_str(&format!("{var}.currentConfigGroup = [\"General\"];\n"));
+        for (key, list) in [
+            ("extraItems", &items.extra_items),
+            ("knownItems", &items.known_items),
+            ("shownItems", &items.shown_items),
+            ("hiddenItems", &items.hidden_items),
+        ] {
+            if !list.is_empty() && !tray.markers.is_key_locked(key) {
+                js.push_str(&format!(
+                    "{var}.writeConfig(\"{key}\", {});\n",
+                    serde_json::to_string(list).unwrap()
+                ));
+            }
+        }
+    }
+    push_containment_config(js, &var, tray);
+    for a in tray.applets_in_order() {
+        let Some(pid) = &a.plugin else {
+            continue;
+        };
+        let w = format!("w_{}_{}", tray.id, a.id);
+        js.push_str(&format!(
+            "safePrint(\"    tray widget {pid} (applet {aid})\");\nvar {w} = widgetIn({var}, {p});\n",
+            aid = a.id,
+            p = serde_json::to_string(pid).unwrap()
+        ));
+        push_widget_config(js, &var, &w, a);
+        push_widget_groups(js, &var, &w, a);
+    }
+    js.push_str("}\n");
+}
+
+/// Replays the configuration groups of `a` onto the JS widget `var` inside
+/// the JS containment `containment`.
+fn push_widget_config(js: &mut String, containment: &str, var: &str, a: &Applet) {
+    let is_systray = a.plugin.as_deref() == Some(SYSTRAY_APPLET);
+    for (group, kv) in &a.config {
+        let skip = |key: &str| {
+            a.is_config_key_locked(group, key)
+                // The new tray points at its own new containment.
+                || (is_systray && key == "SystrayContainmentId")
+        };
+        let obj = config_object(kv, a.config_localized.get(group), skip);
+        js.push_str(&format!(
+            "writeConfigTree({var}, {g}, {obj});\n",
+            g = serde_json::to_string(group).unwrap(),
+        ));
+        let suffix = match group.as_str() {
+            "Configuration" => "/Configuration".to_string(),
+            g => format!("/Configuration/{g}"),
+        };
+        push_expanded_keys(
+            js,
+            &format!(
+                "\"Containments/\" + {containment}.id + \"/Applets/\" + {var}.id + {}",
+                serde_json::to_string(&suffix).unwrap()
+            ),
+            kv,
+            a.config_markers.get(group),
+            skip,
+        );
+    }
+}
+
+/// Has restore-portable.sh write the `[$e]` keys of `kv` back as
+/// `key[$e]=<value as written>`, so they expand on the restoring machine.
+/// `group` is a JS expression for the group's path in appletsrc.
+fn push_expanded_keys(
+    js: &mut String,
+    group: &str,
+    kv: &BTreeMap<String, String>,
+    markers: Option<&Markers>,
+    skip: impl Fn(&str) -> bool,
+) {
+    let Some(m) = markers else {
+        return;
+    };
+    for k in &m.expanded_keys {
+        let Some(v) = kv.get(k).filter(|_| !skip(k)) else {
+            continue;
+        };
+        let line = format!("{}[$e]={}", escape(k, Escape::Key), written_value(k, v, m));
+        js.push_str(&format!(
+            "setAfterQuit({group}, {}, {});\n",
+            serde_json::to_string(k).unwrap(),
+            serde_json::to_string(&line).unwrap()
+        ));
+    }
+}
+
+/// Restores the shortcut and the non-`Configuration` subgroups of `a` onto
+/// the JS widget `var` inside the JS containment `containment`.
+fn push_widget_groups(js: &mut String, containment: &str, var: &str, a: &Applet) {
+    if a.is_locked() {
+        return;
+    }
+    // Registers the binding with KGlobalAccel as well as in [Shortcuts].
+    if let Some(sc) = &a.shortcut {
+        js.push_str(&format!(
+            "{var}.globalShortcut = {};\n",
+            serde_json::to_string(sc).unwrap()
+        ));
+    }
+    for (group, kv) in &a.groups {
+        let obj = config_object(kv, a.groups_localized.get(group), |key| {
+            group == "Shortcuts" && key == "global"
+        });
+        if obj != "{}" {
+            js.push_str(&format!(
+                "writeConfigFile({}, \"Containments/\" + {containment}.id + \"/Applets/\" + {var}.id + {}, {obj});\n",
+                serde_json::to_string(APPLETSRC).unwrap(),
+                serde_json::to_string(&format!("/{group}")).unwrap()
+            ));
+        }
+    }
+}
+
+/// JS object literal of one configuration group, leaving out keys for which
+/// `skip` is true.
+///
+/// Localized variants are written as literal `key[locale]` keys, which
+/// KConfig reads back as translations of `key`.
+fn config_object(
+    kv: &BTreeMap<String, String>,
+    localized: Option<&Localized>,
+    skip: impl Fn(&str) -> bool,
+) -> String {
+    let entries = kv.iter().map(|(k, v)| (k.clone(), v)).chain(
+        localized
+            .into_iter()
+            .flatten()
+            .flat_map(|(k, m)| m.iter().map(move |(l, v)| (format!("{k}[{l}]"), v))),
+    );
+    let mut obj = String::from("{");
+    let mut first = true;
+    for (k, v) in entries {
+        let base = k.split('[').next().unwrap_or(&k);
+        if skip(base) {
+            continue;
+        }
+        if !first {
+            obj.push(',');
+        }
+        first = false;
+        obj.push_str(&format!(
+            "{}:{}",
+            serde_json::to_string(&k).unwrap(),
+            serde_json::to_string(v).unwrap()
+        ));
+    }
+    obj.push('}');
+    obj
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{KWinSummary, VirtualDesktop, VirtualDesktops};
+    use crate::parser::parse_appletsrc;
+    use std::fs;
+
+    const LOCKED_RC: &str = "[Containments][1][$i]
+plugin=org.kde.panel
+
+[Containments][2]
+plugin=org.kde.panel
+
+[Containments][2][Applets][3]
+plugin=org.kde.plasma.kickoff
+
+[Containments][2][Applets][3][Configuration][General]
+icon[$e]=$HOME/icons/start.svg
+label=Menu
+shortcut[$i]=Alt+F1
+";
+
+    #[test]
+    fn restore_script_respects_markers() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("appletsrc");
+        fs::write(&file, LOCKED_RC).unwrap();
+        let js = restore_layout_js(
+            &parse_appletsrc(file.to_str().unwrap()).unwrap(),
+            &RestoreOpts::default(),
+        );
+
+        assert!(js.contains("Skipping panel 1: locked with [$i]"));
+        assert!(!js.contains("p_1 = new Panel()"));
+        assert!(js.contains(r#""label":"Menu""#));
+        assert!(!js.contains("Alt+F1"));
+        assert!(js.contains(
+            r#"setAfterQuit("Containments/" + p_2.id + "/Applets/" + w_2_3.id + "/Configuration/General", "icon", "icon[$e]=$HOME/icons/start.svg");"#
+        ));
+    }
+
+    #[test]
+    fn restore_script_writes_localized_keys() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("appletsrc");
+        fs::write(
+            &file,
+            "[Containments][1]
+plugin=org.kde.panel
+
+[Containments][1][Applets][2]
+plugin=org.kde.plasma.kickoff
+
+[Containments][1][Applets][2][Configuration][General]
+title=Start
+title[de]=Anwendungen
+title[fr_FR]=Démarrer
+",
+        )
+        .unwrap();
+        let js = restore_layout_js(
+            &parse_appletsrc(file.to_str().unwrap()).unwrap(),
+            &RestoreOpts::default(),
+        );
+        assert!(
+            js.contains(r#""title":"Start","title[de]":"Anwendungen","title[fr_FR]":"Démarrer""#)
+        );
+    }
+
+    /// Runs restore-portable.sh with a `qdbus6` that answers `output` and
+    /// returns the appletsrc it left behind and what the script printed.
+    #[cfg(unix)]
+    fn run_portable(appletsrc: &str, output: &str) -> (String, String) {
+        use std::os::unix::fs::PermissionsExt;
+        use std::process::Command;
+
+        let dir = tempfile::tempdir().unwrap();
+        let bundle = dir.path().join("bundle/scripts");
+        let bin = dir.path().join("bin");
+        let config = dir.path().join("home/.config");
+        for d in [&bundle, &bin, &config] {
+            fs::create_dir_all(d).unwrap();
+        }
+        fs::write(bundle.join("restore-portable.sh"), restore_portable_sh()).unwrap();
+        fs::write(bundle.join("restore-layout.js"), "").unwrap();
+        let rc = config.join("plasma-org.kde.plasma.desktop-appletsrc");
+        fs::write(&rc, appletsrc).unwrap();
+        fs::write(dir.path().join("output"), output).unwrap();
+        for (name, body) in [
+            (
+                "qdbus6",
+                format!("cat '{}'", dir.path().join("output").display()),
+            ),
+            ("systemctl", "exit 1".to_string()),
+            ("kquitapp6", String::new()),
+            ("plasmashell", String::new()),
+        ] {
+            let p = bin.join(name);
+            fs::write(&p, format!("#!/bin/sh\n{body}\n")).unwrap();
+            fs::set_permissions(&p, fs::Permissions::from_mode(0o755)).unwrap();
+        }
+        let out = Command::new("bash")
+            .arg(bundle.join("restore-portable.sh"))
+            .env("HOME", dir.path().join("home"))
+            .env_remove("XDG_CONFIG_HOME")
+            .env(
+                "PATH",
+                format!("{}:{}", bin.display(), std::env::var("PATH").unwrap()),
+            )
+            .output()
+            .unwrap();
+        assert!(out.status.success(), "{out:?}");
+        (
+            fs::read_to_string(&rc).unwrap(),
+            String::from_utf8(out.stdout).unwrap(),
+        )
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn portable_restore_writes_marked_lines_after_quit() {
+        let (rc, stdout) = run_portable(
+            "[A]\nicon=$HOME/old\nkeep=1\n\n[B]\nx=1\n\n[C]\ny=1\n",
+            "Done.\n\
+             @set\tA\ticon\ticon[$e]=$HOME/new\n\
+             @set\tB\tpath\tpath[$e]=$HOME/p\n\
+             @set\tD/E\tz\tz[$e]=$HOME/z\n",
+        );
+        assert_eq!(
+            rc,
+            "[A]\nicon[$e]=$HOME/new\nkeep=1\n\n[B]\nx=1\npath[$e]=$HOME/p\n\n[C]\ny=1\n\n[D][E]\nz[$e]=$HOME/z\n"
+        );
+        assert!(stdout.contains("Done.\n"));
+        assert!(!stdout.contains("@set"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn portable_restore_matches_whole_keys_and_groups() {
+        let (rc, _) = run_portable(
+            "[A]\nicon[$e] = $HOME/old\niconSize=2\n\n[A][B]\nicon=x\n\n[A] \nicon=again\n\n[C]\ny=1",
+            "@set\tA\ticon\ticon[$e]=$HOME/new\n@set\tC\tz\tz[$e]=a=b\n",
+        );
+        assert_eq!(
+            rc,
+            "[A]\nicon[$e]=$HOME/new\niconSize=2\n\n[A][B]\nicon=x\n\n[A] \nicon[$e]=$HOME/new\n\n[C]\ny=1\nz[$e]=a=b\n"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn portable_restore_leaves_appletsrc_alone_without_marked_lines() {
+        let rc = "[A]\r\nicon=x\r\n";
+        assert_eq!(run_portable(rc, "Done.\n").0, rc);
+    }
+
+    /// Runs restore-kwin.sh for `kwin` against an empty kwinrc and returns
+    /// what it printed.
+    #[cfg(unix)]
+    fn run_kwin(kwin: &KWinScan) -> String {
+        use std::os::unix::fs::PermissionsExt;
+        use std::process::Command;
+
+        let dir = tempfile::tempdir().unwrap();
+        let scripts = dir.path().join("bundle/scripts");
+        let bin = dir.path().join("bin");
+        for d in [&scripts, &bin] {
+            fs::create_dir_all(d).unwrap();
+        }
+        let sh = restore_kwin_sh(kwin, &KWinPackages::default());
+        fs::write(scripts.join("restore-kwin.sh"), sh).unwrap();
+        for name in ["kreadconfig6", "kwriteconfig6", "qdbus6"] {
+            let p = bin.join(name);
+            fs::write(
+                &p,
+                "#!/bin/sh
+",
+            )
+            .unwrap();
+            fs::set_permissions(&p, fs::Permissions::from_mode(0o755)).unwrap();
+        }
+        let out = Command::new("bash")
+            .arg(scripts.join("restore-kwin.sh"))
+            .env("HOME", dir.path())
+            .env("PATH", format!("{}:/usr/bin:/bin", bin.display()))
+            .output()
+            .unwrap();
+        assert!(out.status.success(), "{out:?}");
+        String::from_utf8(out.stdout).unwrap()
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn kwin_restore_asks_for_a_restart_when_desktops_change() {
+        let mut kwin = KWinScan {
+            kwinrc: String::new(),
+            kwinrulesrc: String::new(),
+            provenance: BTreeMap::new(),
+            summary: KWinSummary::default(),
+            plugins: Vec::new(),
+            window_rules: Vec::new(),
+        };
+        kwin.summary
+            .task_switcher
+            .insert("LayoutName".into(), "thumbnail_grid".into());
+        let restart = "restart KWin (log out and back in)";
+        assert!(!run_kwin(&kwin).contains(restart));
+
+        kwin.summary.virtual_desktops = Some(VirtualDesktops {
+            rows: Some(2),
+            desktops: vec![VirtualDesktop {
+                id: None,
+                name: Some("Web".into()),
+            }],
+        });
+        assert!(run_kwin(&kwin).contains(restart));
+    }
+
+    /// Runs restore-kwin-rules.sh with `bundled` as the bundle's
+    /// kwinrulesrc and returns the machine's kwinrulesrc afterwards.
+    #[cfg(unix)]
+    fn run_kwin_rules(bundled: &str, machine: &str) -> String {
+        use std::process::Command;
+
+        let dir = tempfile::tempdir().unwrap();
+        let scripts = dir.path().join("bundle/scripts");
+        let kwin = dir.path().join("bundle/kwin");
+        let config = dir.path().join("home/.config");
+        for d in [&scripts, &kwin, &config] {
+            fs::create_dir_all(d).unwrap();
+        }
+        fs::write(
+            scripts.join("restore-kwin-rules.sh"),
+            restore_kwin_rules_sh(),
+        )
+        .unwrap();
+        fs::write(kwin.join("kwinrulesrc"), bundled).unwrap();
+        let rc = config.join("kwinrulesrc");
+        fs::write(&rc, machine).unwrap();
+        let out = Command::new("bash")
+            .arg(scripts.join("restore-kwin-rules.sh"))
+            .env("HOME", dir.path().join("home"))
+            .env_remove("XDG_CONFIG_HOME")
+            .env("PATH", "/usr/bin:/bin")
+            .output()
+            .unwrap();
+        assert!(out.status.success(), "{out:?}");
+        fs::read_to_string(&rc).unwrap()
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn kwin_rules_restore_splits_on_unescaped_commas() {
+        let rc = run_kwin_rules(
+            "[a,b]\nwmclass=x\n\n[General]\ncount=1\nrules=a\\,b\n",
+            "[b]\nwmclass=old\n\n[a,b]\nwmclass=stale\n\n[General]\ncount=2\nrules=b,a\\,b\n",
+        );
+        assert_eq!(
+            rc,
+            "[b]\nwmclass=old\n\n[General]\ncount=2\nrules=b,a\\,b\n\n[a,b]\nwmclass=x\n\n"
+        );
+    }
+}
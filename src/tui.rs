@@ -13,7 +13,9 @@ use ratatui::{
     Terminal,
 };
 
-use crate::model::{Containment, Layout};
+use kdesktop_copycat::model::{
+    ConfigEnum, Containment, KWinPluginKind, KWinScan, Layout, Location, Markers, PanelSize,
+};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum Tab {
@@ -120,7 +122,7 @@ impl App {
         match k.code {
             KeyCode::Up => self.plasma_sel = self.plasma_sel.saturating_sub(1),
             KeyCode::Down => {
-                let max = self.layout.containments.len().saturating_sub(1);
+                let max = self.layout.containment_tree().len().saturating_sub(1);
                 self.plasma_sel = (self.plasma_sel + 1).min(max);
             }
             _ => {}
@@ -188,13 +190,20 @@ impl App {
             .constraints([Constraint::Percentage(35), Constraint::Percentage(65)])
             .split(area);
 
-        let items: Vec<ListItem> = self
-            .layout
-            .containments
+        let tree = self.layout.containment_tree();
+        let items: Vec<ListItem> = tree
             .iter()
             .enumerate()
-            .map(|(i, c)| {
-                let title = containment_title(c);
+            .map(|(i, (depth, c))| {
+                let title = if *depth == 0 {
+                    containment_title(c, &self.layout)
+                } else {
+                    format!(
+                        "{}└ {}",
+                        "  ".repeat(*depth),
+                        containment_title(c, &self.layout)
+                    )
+                };
                 if i == self.plasma_sel {
                     ListItem::new(Line::from(Span::styled(
                         title,
@@ -211,8 +220,8 @@ impl App {
             cols[0],
         );
 
-        let detail = if let Some(c) = self.layout.containments.get(self.plasma_sel) {
-            containment_details(c)
+        let detail = if let Some((_, c)) = tree.get(self.plasma_sel) {
+            containment_details(c, &self.layout)
         } else {
             "No containment selected.".to_string()
         };
@@ -267,11 +276,15 @@ impl App {
         This viewer shows:
         • Plasma containments (desktops + panels)
         • Panel widget order (AppletOrder) when present
+        • System tray contents, nested under the panel that holds the tray
+        • Desktop wallpapers (plugin and settings)
+        • Activities: desktops are grouped by the activity they belong to
         • KWin Tier-1 summary:
-        - enabled effects (kwinrc [Plugins] *Enabled=true)
-        - enabled scripts (same)
+        - effects and scripts switched on or off (kwinrc [Plugins]),
+          identified by their package metadata
         - task switcher (kwinrc [TabBox], [TabBoxAlternative])
-        - window rules count (kwinrulesrc numeric groups)
+        - window rules: what they match and set (kwinrulesrc)
+        - virtual desktops: count, rows and names (kwinrc [Desktops])
 
         Notes:
         • Desktop containments often show as plugin org.kde.plasma.folder (Folder View).
@@ -294,26 +307,90 @@ fn block_rounded(title: &str) -> Block<'_> {
         .border_type(BorderType::Rounded)
 }
 
-fn containment_title(c: &Containment) -> String {
-    if c.is_panel {
-        format!(
-            "Panel #{}  ({})",
-            c.id,
-            c.plugin.as_deref().unwrap_or("unknown")
-        )
+fn containment_title(c: &Containment, layout: &Layout) -> String {
+    let screens = &layout.screens;
+    let lock = if c.is_locked() { "  [locked]" } else { "" };
+    let plugin = c.plugin.as_deref().unwrap_or("unknown");
+    let screen = match c.screen() {
+        Some(s) => match screens.connector(s) {
+            Some(conn) => format!(" on screen {s} ({conn})"),
+            None => format!(" on screen {s}"),
+        },
+        None => String::new(),
+    };
+    if let Some(aid) = c.parent_applet {
+        format!("System tray #{}  (in widget #{aid}){lock}", c.id)
+    } else if c.is_panel {
+        let kind = match c.location {
+            Some(l) if l.is_edge() || l == Location::Floating => format!("{} panel", l.label()),
+            _ => "Panel".to_string(),
+        };
+        format!("{kind} #{}{screen}  ({plugin}){lock}", c.id)
+    } else if c.location == Some(Location::Desktop) || c.activity().is_some() {
+        let activity = c
+            .activity()
+            .map(|id| match layout.activity(id) {
+                Some(a) => format!("  · {}", a.name),
+                None => "  · unknown activity".to_string(),
+            })
+            .unwrap_or_default();
+        format!("Desktop #{}{screen}  ({plugin}){activity}{lock}", c.id)
     } else {
-        format!(
-            "Containment #{}  ({})",
-            c.id,
-            c.plugin.as_deref().unwrap_or("unknown")
-        )
+        format!("Containment #{}  ({plugin}){lock}", c.id)
     }
 }
 
-fn containment_details(c: &Containment) -> String {
+fn containment_details(c: &Containment, layout: &Layout) -> String {
+    let screens = &layout.screens;
     let mut out = String::new();
-    out.push_str(&format!("{}\n", containment_title(c)));
+    out.push_str(&format!("{}\n", containment_title(c, layout)));
+    if let Some(id) = c.activity() {
+        match layout.activity(id) {
+            Some(a) => out.push_str(&format!(
+                "activity: {}{}  ({id})\n",
+                a.name,
+                a.icon
+                    .as_deref()
+                    .map(|i| format!("  [{i}]"))
+                    .unwrap_or_default()
+            )),
+            None => out.push_str(&format!("activity: {id} (not in kactivitymanagerdrc)\n")),
+        }
+    }
+    if let Some(o) = c
+        .screen()
+        .and_then(|s| screens.connector(s))
+        .and_then(|conn| screens.outputs.iter().find(|o| o.connector == conn))
+    {
+        out.push_str(&format!(
+            "output: {} {}x{}+{}+{}{}{}\n",
+            o.connector,
+            o.width,
+            o.height,
+            o.x,
+            o.y,
+            o.name
+                .as_deref()
+                .map(|n| format!("  {n}"))
+                .unwrap_or_default(),
+            if o.primary { "  (primary)" } else { "" }
+        ));
+    }
     out.push_str(&format!("is_panel: {}\n", c.is_panel));
+    if c.is_locked() {
+        out.push_str("locked: [$i] (restore leaves this containment alone)\n");
+    } else if !c.markers.immutable_keys.is_empty() {
+        out.push_str("locked keys: ");
+        out.push_str(
+            &c.markers
+                .immutable_keys
+                .iter()
+                .cloned()
+                .collect::<Vec<_>>()
+                .join(", "),
+        );
+        out.push('\n');
+    }
     out.push('\n');
 
     for k in [
@@ -324,7 +401,62 @@ fn containment_details(c: &Containment) -> String {
         "plugin",
     ] {
         if let Some(v) = c.meta.get(k) {
-            out.push_str(&format!("{k}={v}\n"));
+            let decoded = match k {
+                "location" => c.location.map(ConfigEnum::name),
+                "formfactor" => c.form_factor.map(ConfigEnum::name),
+                "immutability" => c.immutability.map(ConfigEnum::name),
+                _ => None,
+            };
+            let decoded = decoded.map(|d| format!(" ({d})")).unwrap_or_default();
+            match c.provenance.get(k) {
+                Some(file) => out.push_str(&format!("{k}={v}{decoded}  (from {file})\n")),
+                None => out.push_str(&format!("{k}={v}{decoded}\n")),
+            }
+        }
+    }
+
+    if let Some(g) = &c.geometry {
+        out.push('\n');
+        out.push_str("Geometry (plasmashellrc):\n");
+        if let Some(m) = g.length_mode {
+            out.push_str(&format!("  length mode: {}\n", m.script_name()));
+        }
+        if let Some(a) = g.alignment {
+            out.push_str(&format!("  alignment: {}\n", a.script_name()));
+        }
+        if let Some(h) = g.hiding {
+            out.push_str(&format!("  hiding: {}\n", h.script_name()));
+        }
+        if let Some(f) = g.floating {
+            out.push_str(&format!("  floating: {f}\n"));
+        }
+        if !g.defaults.is_empty() {
+            out.push_str(&format!("  default: {}\n", panel_size(&g.defaults)));
+        }
+        for (screen, size) in &g.screens {
+            out.push_str(&format!("  {screen}: {}\n", panel_size(size)));
+        }
+    }
+
+    if let Some(wp) = &c.wallpaper {
+        out.push('\n');
+        out.push_str(&format!("Wallpaper: {}\n", wp.plugin));
+        for (group, kv) in &wp.config {
+            for (k, v) in kv {
+                let v = shown_value(wp.config_markers.get(group), k, v);
+                out.push_str(&format!("  {group}/{k}={v}\n"));
+            }
+        }
+    }
+
+    if !c.config.is_empty() {
+        out.push('\n');
+        out.push_str("Settings:\n");
+        for (group, kv) in &c.config {
+            for (k, v) in kv {
+                let v = shown_value(c.config_markers.get(group), k, v);
+                out.push_str(&format!("  {group}/{k}={v}\n"));
+            }
         }
     }
 
@@ -341,27 +473,96 @@ fn containment_details(c: &Containment) -> String {
         out.push('\n');
     }
 
+    if let Some(tray) = &c.systray {
+        for (label, items) in [
+            ("Enabled", &tray.extra_items),
+            ("Always shown", &tray.shown_items),
+            ("Always hidden", &tray.hidden_items),
+        ] {
+            if !items.is_empty() {
+                out.push('\n');
+                out.push_str(&format!("{label}:\n"));
+                for item in items {
+                    out.push_str(&format!("  • {item}\n"));
+                }
+            }
+        }
+    }
+
     out.push('\n');
     out.push_str("Widgets:\n");
     for a in c.applets_in_order() {
         let plug = a.plugin.as_deref().unwrap_or("unknown");
-        out.push_str(&format!("  • #{}  {}\n", a.id, plug));
+        let lock = if a.is_locked() { "  [locked]" } else { "" };
+        let tray = a
+            .child_containment
+            .map(|id| format!("  → tray #{id}"))
+            .unwrap_or_default();
+        let shortcut = a
+            .shortcut
+            .as_ref()
+            .map(|sc| format!("  ⌨ {sc}"))
+            .unwrap_or_default();
+        out.push_str(&format!("  • #{}  {}{lock}{tray}{shortcut}\n", a.id, plug));
+    }
+
+    let defaults = c.provenance.len() + c.applets.iter().map(|a| a.provenance.len()).sum::<usize>();
+    if defaults > 0 {
+        out.push('\n');
+        out.push_str("From system defaults (not in your appletsrc):\n");
+        for (k, file) in &c.provenance {
+            out.push_str(&format!("  {k}  ← {file}\n"));
+        }
+        for a in &c.applets {
+            for (k, file) in &a.provenance {
+                out.push_str(&format!("  #{} {k}  ← {file}\n", a.id));
+            }
+        }
     }
 
     out
 }
 
+/// `value` as stored, followed by what it expands to for `[$e]` keys.
+fn shown_value(markers: Option<&Markers>, key: &str, value: &str) -> String {
+    match markers.and_then(|m| m.expand(key, value)) {
+        Some(expanded) => format!("{value}  (→ {expanded})"),
+        None => value.to_string(),
+    }
+}
+
+fn panel_size(s: &PanelSize) -> String {
+    let mut parts = Vec::new();
+    if let Some(t) = s.thickness {
+        parts.push(format!("thickness {t}"));
+    }
+    if let Some(l) = s.length {
+        parts.push(format!("length {l}"));
+    }
+    match (s.min_length, s.max_length) {
+        (Some(lo), Some(hi)) => parts.push(format!("range {lo}–{hi}")),
+        (Some(lo), None) => parts.push(format!("min {lo}")),
+        (None, Some(hi)) => parts.push(format!("max {hi}")),
+        (None, None) => {}
+    }
+    if let Some(o) = s.offset {
+        parts.push(format!("offset {o}"));
+    }
+    parts.join(", ")
+}
+
 fn kwin_left_items(layout: &Layout) -> Vec<String> {
     if layout.kwin.is_none() {
         return vec!["(kwinrc not found)".to_string()];
     }
     vec![
         "Tier-1 Summary".to_string(),
-        "Enabled Effects".to_string(),
-        "Enabled Scripts".to_string(),
+        "Effects".to_string(),
+        "Scripts".to_string(),
         "Task Switcher".to_string(),
         "Task Switcher (Alt)".to_string(),
         "Window Rules".to_string(),
+        "Virtual Desktops".to_string(),
     ]
 }
 
@@ -377,6 +578,34 @@ fn kv_map_to_lines(map: &std::collections::BTreeMap<String, String>, max: usize)
     out
 }
 
+/// Effects or scripts from `[Plugins]`, switched on ones first.
+fn plugin_list(title: &str, kw: &KWinScan, kind: KWinPluginKind) -> String {
+    let mut out = format!("{title}\n\n");
+    for enabled in [true, false] {
+        let plugins: Vec<_> = kw
+            .plugins
+            .iter()
+            .filter(|p| p.kind == Some(kind) && p.enabled == enabled)
+            .collect();
+        out.push_str(if enabled {
+            "Enabled:\n"
+        } else {
+            "\nDisabled:\n"
+        });
+        if plugins.is_empty() {
+            out.push_str("  (none)\n");
+        }
+        for p in plugins {
+            out.push_str(&format!("  • {} ({})", p.label(), p.id));
+            if p.enabled_by_default == Some(enabled) {
+                out.push_str("  [default]");
+            }
+            out.push('\n');
+        }
+    }
+    out
+}
+
 fn kwin_right_panel(layout: &Layout, sel: usize) -> String {
     let Some(kw) = &layout.kwin else {
         return "No kwinrc loaded.\nTip: make sure ~/.config/kwinrc exists.".to_string();
@@ -389,10 +618,27 @@ fn kwin_right_panel(layout: &Layout, sel: usize) -> String {
             let mut out = String::new();
             out.push_str("Tier-1 Summary\n\n");
             out.push_str(&format!("kwinrc: {}\n", kw.kwinrc));
-            out.push_str(&format!("kwinrulesrc: {}\n\n", kw.kwinrulesrc));
+            out.push_str(&format!("kwinrulesrc: {}\n", kw.kwinrulesrc));
+            if !kw.provenance.is_empty() {
+                out.push_str(&format!(
+                    "Values from system defaults: {}\n",
+                    kw.provenance.len()
+                ));
+            }
+            out.push('\n');
             out.push_str(&format!("Enabled effects: {}\n", s.enabled_effects.len()));
             out.push_str(&format!("Enabled scripts: {}\n", s.enabled_scripts.len()));
-            out.push_str(&format!("Window rules: {}\n\n", s.window_rules_count));
+            if !s.unknown_plugins.is_empty() {
+                out.push_str(&format!(
+                    "Unknown (no metadata found): {}\n",
+                    s.unknown_plugins.join(", ")
+                ));
+            }
+            out.push_str(&format!("Window rules: {}\n", s.window_rules_count));
+            if let Some(vd) = &s.virtual_desktops {
+                out.push_str(&format!("Virtual desktops: {}\n", vd.desktops.len()));
+            }
+            out.push('\n');
 
             if !s.enabled_effects.is_empty() {
                 out.push_str("Top effects:\n");
@@ -419,30 +665,8 @@ fn kwin_right_panel(layout: &Layout, sel: usize) -> String {
             }
             out
         }
-        1 => {
-            let mut out = String::new();
-            out.push_str("Enabled Effects\n\n");
-            if s.enabled_effects.is_empty() {
-                out.push_str("(none detected)\n");
-            } else {
-                for e in &s.enabled_effects {
-                    out.push_str(&format!("• {e}\n"));
-                }
-            }
-            out
-        }
-        2 => {
-            let mut out = String::new();
-            out.push_str("Enabled Scripts\n\n");
-            if s.enabled_scripts.is_empty() {
-                out.push_str("(none detected)\n");
-            } else {
-                for e in &s.enabled_scripts {
-                    out.push_str(&format!("• {e}\n"));
-                }
-            }
-            out
-        }
+        1 => plugin_list("Effects", kw, KWinPluginKind::Effect),
+        2 => plugin_list("Scripts", kw, KWinPluginKind::Script),
         3 => {
             let mut out = String::new();
             out.push_str("Task Switcher (TabBox)\n\n");
@@ -463,10 +687,53 @@ fn kwin_right_panel(layout: &Layout, sel: usize) -> String {
             }
             out
         }
-        5 => format!(
-            "Window Rules\n\nRules detected in kwinrulesrc: {}\n\n(Note: Tier-1 count of numeric groups like [1], [2], ...)",
-                     s.window_rules_count
-        ),
+        5 => {
+            let mut out = String::new();
+            out.push_str("Window Rules\n\n");
+            if kw.window_rules.is_empty() {
+                out.push_str("(no rules in kwinrulesrc)\n");
+            }
+            for r in &kw.window_rules {
+                let name = if r.description.is_empty() {
+                    &r.id
+                } else {
+                    &r.description
+                };
+                out.push_str(&format!("• {name}\n"));
+                out.push_str(&format!("  matches: {}\n", r.match_label()));
+                for (key, p) in &r.properties {
+                    let policy = p.policy.map_or("?", |p| p.name());
+                    out.push_str(&format!("  {key}={} ({policy})\n", p.value));
+                }
+                out.push('\n');
+            }
+            out
+        }
+        6 => {
+            let mut out = String::new();
+            out.push_str("Virtual Desktops\n\n");
+            let Some(vd) = &s.virtual_desktops else {
+                out.push_str("(no Desktops section found)\n");
+                return out;
+            };
+            out.push_str(&format!("Desktops: {}\n", vd.desktops.len()));
+            if let Some(rows) = vd.rows {
+                out.push_str(&format!("Rows: {rows}\n"));
+            }
+            out.push('\n');
+            for (i, d) in vd.desktops.iter().enumerate() {
+                let name = d
+                    .name
+                    .clone()
+                    .unwrap_or_else(|| format!("Desktop {}", i + 1));
+                out.push_str(&format!("{}. {name}", i + 1));
+                if let Some(id) = &d.id {
+                    out.push_str(&format!("  ({id})"));
+                }
+                out.push('\n');
+            }
+            out
+        }
         _ => "Select an item on the left.".to_string(),
     }
 }
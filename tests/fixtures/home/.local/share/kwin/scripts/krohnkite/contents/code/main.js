@@ -0,0 +1 @@
+// Tiling logic lives here.
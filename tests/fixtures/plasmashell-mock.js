@@ -0,0 +1,71 @@
+// Just enough of the Plasma scripting API to run restore-layout.js under
+// node. When the script ends, appletsrc is saved the way plasmashell saves
+// it on quit: every panel's lastScreen is the screen it is really on.
+var fs = require("fs");
+var containments = [];
+var nextId = 100;
+
+function print(x) { process.stdout.write(String(x) + "\n"); }
+function userDataPath() { return "/nonexistent"; }
+function screenGeometry() { return { width: 1920, height: 1080 }; }
+function activities() { return []; }
+function currentActivity() { return ""; }
+function desktopsForActivity() { return []; }
+function desktopForScreen() { return null; }
+function desktopById() { return null; }
+
+function Item() {
+  this.id = nextId++;
+  this.currentConfigGroup = [];
+  this.config = {};
+}
+Item.prototype.writeConfig = function (k, v) {
+  var g = this.currentConfigGroup.join("/");
+  (this.config[g] = this.config[g] || {})[k] = String(v);
+};
+Item.prototype.readConfig = function (k) {
+  var g = this.config[this.currentConfigGroup.join("/")] || {};
+  return g[k];
+};
+
+function Panel() {
+  Item.call(this);
+  // Panels cannot be moved from a script; new ones land on screen 0.
+  this.screen = 0;
+  this.applets = [];
+  containments.push(this);
+}
+Panel.prototype = Object.create(Item.prototype);
+Panel.prototype.addWidget = function (plugin) {
+  var w = new Item();
+  w.plugin = plugin;
+  this.applets.push(w);
+  return w;
+};
+Panel.prototype.widgets = function () { return []; };
+
+function groups(prefix, config) {
+  var out = "";
+  for (var g in config) {
+    if (!g) continue;
+    out += prefix + "[" + g.split("/").join("][") + "]\n";
+    for (var k in config[g]) out += k + "=" + config[g][k] + "\n";
+    out += "\n";
+  }
+  return out;
+}
+
+process.on("exit", function () {
+  var out = "";
+  containments.forEach(function (c) {
+    var cpath = "[Containments][" + c.id + "]";
+    out += cpath + "\nlastScreen=" + c.screen + "\nplugin=org.kde.panel\n\n";
+    out += groups(cpath, c.config);
+    c.applets.forEach(function (w) {
+      var apath = cpath + "[Applets][" + w.id + "]";
+      out += apath + "\nplugin=" + w.plugin + "\n\n";
+      out += groups(apath + "[Configuration]", w.config);
+    });
+  });
+  fs.writeFileSync(process.env.XDG_CONFIG_HOME + "/plasma-org.kde.plasma.desktop-appletsrc", out);
+});
@@ -0,0 +1,643 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use kdesktop_copycat::activities;
+use kdesktop_copycat::input::{self, ConfigFiles};
+use kdesktop_copycat::kwin;
+use kdesktop_copycat::model::{
+    FormFactor, KWinPluginKind, Location, MatchKind, PanelHiding, RulePolicy,
+};
+use kdesktop_copycat::parser::{self, APPLETSRC};
+use kdesktop_copycat::paths::Paths;
+use kdesktop_copycat::scripts::{self, RestoreOpts};
+use kdesktop_copycat::{schema, Exporter, Layout, SCHEMA_VERSION};
+
+fn home() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/home")
+}
+
+fn appletsrc() -> String {
+    home().join(".config").join(APPLETSRC).display().to_string()
+}
+
+fn load() -> Layout {
+    input::load_config(&Paths::with_root(&home()), ConfigFiles::default()).unwrap()
+}
+
+#[test]
+fn loads_panels_tray_and_desktop() {
+    let layout = load();
+    assert_eq!(layout.schema_version, SCHEMA_VERSION);
+    assert!(layout.diagnostics.is_empty(), "{:?}", layout.diagnostics);
+
+    let panel = layout.containment(1).unwrap();
+    assert!(panel.is_panel);
+    assert_eq!(panel.location, Some(Location::BottomEdge));
+    assert_eq!(panel.form_factor, Some(FormFactor::Horizontal));
+    let widgets: Vec<_> = panel
+        .applets_in_order()
+        .iter()
+        .map(|a| a.plugin.as_deref().unwrap())
+        .collect();
+    assert_eq!(
+        widgets,
+        [
+            "org.kde.plasma.kickoff",
+            "org.kde.plasma.systemtray",
+            "org.kde.plasma.digitalclock"
+        ]
+    );
+
+    let kickoff = &panel.applets[0];
+    assert_eq!(kickoff.shortcut.as_deref(), Some("Alt+F1"));
+    assert_eq!(kickoff.config["General"]["icon"], "start-here-kde-symbolic");
+
+    let geometry = panel.geometry.as_ref().unwrap();
+    assert_eq!(geometry.hiding, Some(PanelHiding::AutoHide));
+    assert_eq!(geometry.defaults.thickness, Some(44));
+    assert_eq!(layout.screens.connector(0), Some("DP-1"));
+
+    let tray_applet = panel.applets.iter().find(|a| a.id == 3).unwrap();
+    assert_eq!(tray_applet.child_containment, Some(4));
+    let tray = layout.containment(4).unwrap();
+    assert!(!tray.is_panel);
+    assert_eq!(tray.parent_applet, Some(3));
+    assert_eq!(
+        tray.systray.as_ref().unwrap().hidden_items,
+        ["org.kde.plasma.clipboard"]
+    );
+
+    let desktop = layout.containment(7).unwrap();
+    assert_eq!(desktop.wallpaper.as_ref().unwrap().plugin, "org.kde.image");
+    assert_eq!(desktop.config["General"]["ToolBoxButtonState"], "topcenter");
+    assert_eq!(layout.activities.len(), 1);
+    assert_eq!(layout.activities[0].containments, [7]);
+    assert!(layout.activities[0].current);
+    assert_eq!(
+        layout.action_plugins["0"]["MiddleButton;NoModifier"],
+        "org.kde.paste"
+    );
+
+    let kwin = layout.kwin.as_ref().unwrap();
+    assert_eq!(kwin.summary.task_switcher["LayoutName"], "thumbnail_grid");
+    assert_eq!(kwin.summary.window_rules_count, 2);
+
+    let desktops = kwin.summary.virtual_desktops.as_ref().unwrap();
+    assert_eq!(desktops.rows, Some(1));
+    let names: Vec<_> = desktops
+        .desktops
+        .iter()
+        .map(|d| d.name.as_deref().unwrap())
+        .collect();
+    assert_eq!(names, ["Main", "Web"]);
+    assert!(desktops.desktops[1]
+        .id
+        .as_deref()
+        .unwrap()
+        .starts_with("7d8e9f0a"));
+}
+
+#[test]
+fn classifies_kwin_plugins_by_metadata() {
+    let layout = load();
+    let kwin = layout.kwin.as_ref().unwrap();
+    assert_eq!(kwin.summary.enabled_effects, ["blur"]);
+    assert_eq!(kwin.summary.disabled_effects, ["desktopgrid", "slide"]);
+    assert_eq!(kwin.summary.enabled_scripts, ["krohnkite"]);
+    assert_eq!(kwin.summary.unknown_plugins, ["mysteryeffect"]);
+
+    let krohnkite = kwin.plugins.iter().find(|p| p.id == "krohnkite").unwrap();
+    assert_eq!(krohnkite.kind, Some(KWinPluginKind::Script));
+    assert_eq!(krohnkite.label(), "Kröhnkite");
+    assert_eq!(krohnkite.enabled_by_default, Some(false));
+    assert!(krohnkite
+        .metadata
+        .as_ref()
+        .unwrap()
+        .ends_with("metadata.json"));
+
+    let slide = kwin.plugins.iter().find(|p| p.id == "slide").unwrap();
+    assert!(!slide.enabled);
+    assert_eq!(slide.enabled_by_default, Some(true));
+    assert!(slide.metadata.is_none());
+
+    // Compiled into KWin, known from its builtin-effects metadata
+    let grid = kwin.plugins.iter().find(|p| p.id == "desktopgrid").unwrap();
+    assert_eq!(grid.kind, Some(KWinPluginKind::Effect));
+    assert_eq!(grid.label(), "Desktop Grid");
+    assert_eq!(grid.enabled_by_default, Some(true));
+    assert!(grid.metadata.is_none());
+}
+
+#[test]
+fn reads_window_rules_in_index_order() {
+    let layout = load();
+    let rules = &layout.kwin.as_ref().unwrap().window_rules;
+    assert_eq!(rules.len(), 2);
+
+    let firefox = &rules[0];
+    assert_eq!(firefox.id, "1");
+    let class = firefox.wmclass.as_ref().unwrap();
+    assert_eq!(
+        (class.value.as_str(), class.kind),
+        ("firefox", Some(MatchKind::Exact))
+    );
+    assert_eq!(firefox.properties["desktops"].value, "2");
+    assert_eq!(
+        firefox.properties["desktops"].policy,
+        Some(RulePolicy::ApplyInitially)
+    );
+
+    let konsole = &rules[1];
+    assert!(konsole.wmclass_complete);
+    assert_eq!(
+        konsole.title.as_ref().unwrap().kind,
+        Some(MatchKind::Substring)
+    );
+    assert_eq!(konsole.types, Some(1));
+    assert_eq!(konsole.properties["above"].policy, Some(RulePolicy::Force));
+    assert_eq!(konsole.properties.len(), 1);
+}
+
+#[test]
+fn kwinrules_fragment_renames_numbered_rules() {
+    let layout = load();
+    let rules = &layout.kwin.as_ref().unwrap().window_rules;
+    let fragment = kwin::kwinrules_fragment(rules);
+
+    // Numbered groups would clobber the target's own [1]; UUIDs are kept.
+    assert!(!fragment.contains("[1]\n"));
+    let renamed = fragment.lines().next().unwrap();
+    assert!(renamed.starts_with("[imported-"), "{renamed}");
+    let id = renamed.trim_matches(['[', ']']);
+    assert!(fragment.ends_with(&format!(
+        "[General]\ncount=2\nrules={id},3f2d1c4e-8a51-4c2b-9e0f-5b7a6d1e2c3f\n"
+    )));
+    assert!(fragment.contains("aboverule=2\n"));
+    assert!(fragment.contains("wmclasscomplete=true\n"));
+    assert_eq!(kwin::kwinrules_fragment(rules), fragment);
+}
+
+#[test]
+fn writes_appletsrc_back_unchanged() {
+    let layout = parser::parse_appletsrc(&appletsrc()).unwrap();
+    let original = fs::read_to_string(appletsrc()).unwrap();
+    assert_eq!(parser::write_appletsrc(&layout), original);
+}
+
+#[test]
+fn layout_json_round_trips() {
+    let layout = load();
+    let json = serde_json::to_string_pretty(&layout).unwrap();
+    let back = schema::load_layout_json(&json, "layout.json").unwrap();
+    assert_eq!(serde_json::to_string_pretty(&back).unwrap(), json);
+}
+
+#[test]
+fn migrates_unversioned_layout_json() {
+    let v0 = r#"{
+        "source_file": "appletsrc",
+        "containments": [{
+            "id": 1, "plugin": "org.kde.panel", "is_panel": true,
+            "meta": {"location": "4", "formfactor": "2", "Name[de]": "Leiste"},
+            "applets": [{
+                "id": 2, "plugin": "org.kde.plasma.kickoff", "meta": {},
+                "config": {"General": {"label": "\\sMenu", "icon[$i]": "kde"}}
+            }]
+        }]
+    }"#;
+    let layout = schema::load_layout_json(v0, "old.json").unwrap();
+    assert_eq!(layout.schema_version, SCHEMA_VERSION);
+
+    let panel = layout.containment(1).unwrap();
+    assert_eq!(panel.location, Some(Location::BottomEdge));
+    assert_eq!(panel.localized["Name"]["de"], "Leiste");
+    assert!(!panel.meta.contains_key("Name[de]"));
+
+    let kickoff = &panel.applets[0];
+    assert_eq!(kickoff.config["General"]["label"], " Menu");
+    assert!(kickoff.is_config_key_locked("General", "icon"));
+}
+
+#[test]
+fn rejects_newer_layout_json() {
+    let json = format!(
+        r#"{{"schema_version": {}, "source_file": "x", "containments": []}}"#,
+        SCHEMA_VERSION + 1
+    );
+    assert!(schema::load_layout_json(&json, "new.json").is_err());
+}
+
+#[test]
+fn json_schema_covers_the_model() {
+    let schema = schema::json_schema();
+    let properties = &schema["properties"];
+    for field in ["schema_version", "containments", "activities", "screens"] {
+        assert!(properties.get(field).is_some(), "missing {field}");
+    }
+    assert!(schema["$defs"]["Containment"]["properties"]
+        .get("wallpaper")
+        .is_some());
+}
+
+#[test]
+fn restore_script_adds_panel_widgets() {
+    let layout = load();
+    let js = scripts::restore_layout_js(&layout, &RestoreOpts::default());
+    let kickoff = js.find(r#"addWidget("org.kde.plasma.kickoff")"#).unwrap();
+    let clock = js
+        .find(r#"addWidget("org.kde.plasma.digitalclock")"#)
+        .unwrap();
+    assert!(kickoff < clock);
+    assert!(js.contains(r#".globalShortcut = "Alt+F1";"#));
+    assert!(js.contains("widgetIn(t_4, \"org.kde.plasma.volume\")"));
+}
+
+#[test]
+fn exporter_writes_a_bundle_that_loads_back() {
+    let layout = load();
+    let out = tempfile::tempdir().unwrap();
+    let bundle = Exporter::new(out.path())
+        .paths(Paths::with_root(&home()))
+        .bundle_plasmoids(false)
+        .map_screen("HDMI-A-9", "DP-2")
+        .export(&layout)
+        .unwrap();
+
+    assert!(bundle.dir.starts_with(out.path()));
+    assert_eq!(bundle.warnings.len(), 1);
+    assert!(bundle.warnings[0].contains("HDMI-A-9"));
+    for file in [
+        "layout.json",
+        "activities.tsv",
+        "scripts/restore-layout.js",
+        "scripts/restore-portable.sh",
+        "scripts/restore-snapshot.sh",
+        "scripts/restore-kwin-rules.sh",
+        "scripts/restore-kwin.sh",
+        "kwin/kwinrulesrc",
+    ] {
+        assert!(bundle.dir.join(file).is_file(), "missing {file}");
+    }
+    assert!(!bundle.dir.join("plasmoids").exists());
+
+    // The user's tiling script and Aurorae theme travel with the bundle.
+    let kwin = bundle.dir.join("kwin");
+    assert!(kwin
+        .join("scripts/krohnkite/contents/code/main.js")
+        .is_file());
+    assert!(kwin.join("aurorae/Sweet-Dark/Sweet-Darkrc").is_file());
+    let restore_kwin = fs::read_to_string(bundle.dir.join("scripts/restore-kwin.sh")).unwrap();
+    let install = restore_kwin
+        .find(r#"install_package KWin/Script "$KWIN_DIR/scripts/"'krohnkite'"#)
+        .unwrap();
+    let enable = restore_kwin
+        .find("kwinrc 'Plugins' 'krohnkiteEnabled' 'true'")
+        .unwrap();
+    assert!(install < enable);
+    assert!(
+        restore_kwin.contains("kwinrc 'org.kde.kdecoration2' 'theme' '__aurorae__svg__Sweet-Dark'")
+    );
+    // Disabled plugins and the task switcher are written back too.
+    assert!(restore_kwin.contains("kwinrc 'Plugins' 'slideEnabled' 'false'"));
+    assert!(restore_kwin.contains("kwinrc 'TabBox' 'LayoutName' 'thumbnail_grid'"));
+    assert!(restore_kwin.contains("kwinrc 'Desktops' 'Number' '2'"));
+    assert!(restore_kwin.contains("kwinrc 'Desktops' 'Name_2' 'Web'"));
+    assert_eq!(
+        fs::read_to_string(bundle.dir.join("snapshot").join(APPLETSRC)).unwrap(),
+        fs::read_to_string(appletsrc()).unwrap()
+    );
+
+    let loaded = input::load(&bundle.dir).unwrap();
+    assert_eq!(loaded.bundle_dir.as_deref(), Some(bundle.dir.as_path()));
+    assert_eq!(
+        serde_json::to_value(&loaded.layout).unwrap(),
+        serde_json::to_value(&layout).unwrap()
+    );
+    // The snapshot came back as the syntax tree.
+    assert_eq!(
+        parser::write_appletsrc(&loaded.layout),
+        fs::read_to_string(appletsrc()).unwrap()
+    );
+}
+
+#[test]
+fn writes_odd_appletsrc_back_unchanged() {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/odd/appletsrc");
+    let layout = parser::parse_appletsrc(&path.display().to_string()).unwrap();
+    assert_eq!(layout.containments.len(), 1);
+    assert_eq!(layout.containments[0].applets.len(), 1);
+    let original = fs::read(&path).unwrap();
+    assert_eq!(parser::write_appletsrc(&layout).into_bytes(), original);
+}
+
+#[test]
+fn layout_json_keeps_value_escaping() {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/odd/appletsrc");
+    let layout = parser::parse_appletsrc(&path.display().to_string()).unwrap();
+    let json = serde_json::to_string(&layout).unwrap();
+    let back = schema::load_layout_json(&json, "layout.json").unwrap();
+    let written = parser::write_appletsrc(&back);
+    assert!(
+        written.contains("label=tab\\there\\\\back\\x41\n"),
+        "{written}"
+    );
+    assert!(written.contains("icon=\\sstart-here\\s\n"), "{written}");
+}
+
+#[test]
+fn locked_groups_survive_layout_json() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join(APPLETSRC);
+    let raw = "[Containments][1][$i]\nplugin=org.kde.panel\n\n[Containments][2]\nplugin=org.kde.desktop\n";
+    fs::write(&path, raw).unwrap();
+    let layout = parser::parse_appletsrc(&path.display().to_string()).unwrap();
+    let json = serde_json::to_string(&layout).unwrap();
+    let back = schema::load_layout_json(&json, "layout.json").unwrap();
+    assert_eq!(parser::write_appletsrc(&back), raw);
+}
+
+/// A panel on screen 0 (`DP-1`) with a launcher whose icon is an `[$e]` path.
+const PANEL_RC: &str = "[Containments][7]
+formfactor=2
+lastScreen=0
+location=4
+plugin=org.kde.panel
+
+[Containments][7][Applets][8]
+plugin=org.kde.plasma.kickoff
+
+[Containments][7][Applets][8][Configuration][General]
+icon[$e]=$HOME/icons/start\\x2esvg
+";
+
+fn panel_layout() -> Layout {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join(APPLETSRC);
+    fs::write(&path, PANEL_RC).unwrap();
+    let mut layout = parser::parse_appletsrc(&path.display().to_string()).unwrap();
+    layout.screens.connectors.insert(0, "DP-1".to_string());
+    layout
+}
+
+/// Runs restore-portable.sh for `layout` against stub KDE tools; node runs
+/// restore-layout.js on top of `fixtures/plasmashell-mock.js`. Returns the
+/// resulting appletsrc, or `None` when node is not installed.
+#[cfg(unix)]
+fn restore_portable(layout: &Layout, opts: &RestoreOpts, args: &[&str]) -> Option<String> {
+    use std::os::unix::fs::PermissionsExt;
+    use std::process::Command;
+
+    if Command::new("node").arg("--version").output().is_err() {
+        eprintln!("node not found; skipping");
+        return None;
+    }
+    let dir = tempfile::tempdir().unwrap();
+    let scripts_dir = dir.path().join("bundle/scripts");
+    fs::create_dir_all(&scripts_dir).unwrap();
+    let script = scripts_dir.join("restore-portable.sh");
+    fs::write(&script, scripts::restore_portable_sh()).unwrap();
+    fs::write(
+        scripts_dir.join("restore-layout.js"),
+        scripts::restore_layout_js(layout, opts),
+    )
+    .unwrap();
+
+    let config = dir.path().join("config");
+    fs::create_dir_all(&config).unwrap();
+    fs::write(
+        config.join("plasmashellrc"),
+        "[ScreenConnectors]\n0=DP-1\n1=HDMI-A-1\n",
+    )
+    .unwrap();
+
+    let bin = dir.path().join("bin");
+    fs::create_dir_all(&bin).unwrap();
+    let mock = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/plasmashell-mock.js");
+    let run = dir.path().join("run.js");
+    for (name, body) in [
+        (
+            "qdbus6",
+            format!(
+                "case \"$*\" in *evaluateScript*) ;; *) exit 1 ;; esac\nfor last; do :; done\n{{ cat '{}'; printf '%s\\n' \"$last\"; }} > '{}'\nexec node '{}'\n",
+                mock.display(),
+                run.display(),
+                run.display()
+            ),
+        ),
+        ("systemctl", "exit 1\n".to_string()),
+        ("kquitapp6", "exit 0\n".to_string()),
+        ("plasmashell", "exit 0\n".to_string()),
+    ] {
+        let path = bin.join(name);
+        fs::write(&path, format!("#!/bin/sh\n{body}")).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+    }
+
+    let status = Command::new("bash")
+        .arg(&script)
+        .args(args)
+        .env(
+            "PATH",
+            format!("{}:{}", bin.display(), std::env::var("PATH").unwrap()),
+        )
+        .env("HOME", dir.path())
+        .env("XDG_CONFIG_HOME", &config)
+        .env("XDG_DATA_HOME", dir.path().join("data"))
+        .status()
+        .unwrap();
+    assert!(status.success());
+    Some(fs::read_to_string(config.join(APPLETSRC)).unwrap())
+}
+
+#[test]
+fn expanded_keys_stay_unexpanded() {
+    let layout = panel_layout();
+    let general = &layout.containment(7).unwrap().applets[0].config["General"];
+    assert_eq!(general["icon"], "$HOME/icons/start.svg");
+
+    // The snapshot keeps the line as written.
+    assert!(parser::write_appletsrc(&layout).contains("icon[$e]=$HOME/icons/start\\x2esvg\n"));
+
+    // The portable restore writes the key back with its marker.
+    #[cfg(unix)]
+    if let Some(restored) = restore_portable(&layout, &RestoreOpts::default(), &[]) {
+        assert!(
+            restored.contains(
+                "[Containments][100][Applets][101][Configuration][General]\nicon[$e]=$HOME/icons/start\\x2esvg\n"
+            ),
+            "{restored}"
+        );
+    }
+}
+
+#[test]
+fn mapped_panel_lands_on_the_target_output() {
+    let layout = panel_layout();
+    let lands_on_hdmi = |restored: String| {
+        assert!(
+            restored.starts_with("[Containments][100]\nlastScreen=1\n"),
+            "{restored}"
+        );
+    };
+
+    // Chosen at export time...
+    let mut opts = RestoreOpts::default();
+    opts.screen_map
+        .insert("DP-1".to_string(), "HDMI-A-1".to_string());
+    #[cfg(unix)]
+    if let Some(restored) = restore_portable(&layout, &opts, &[]) {
+        lands_on_hdmi(restored);
+    }
+
+    // ...or when restoring.
+    #[cfg(unix)]
+    if let Some(restored) = restore_portable(
+        &layout,
+        &RestoreOpts::default(),
+        &["--map-screen", "DP-1=HDMI-A-1"],
+    ) {
+        lands_on_hdmi(restored);
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn bad_map_screen_stops_before_installing_anything() {
+    use std::os::unix::fs::PermissionsExt;
+    use std::process::Command;
+
+    let dir = tempfile::tempdir().unwrap();
+    let bundle = dir.path().join("bundle");
+    fs::create_dir_all(bundle.join("scripts")).unwrap();
+    fs::create_dir_all(bundle.join("plasmoids/org.example.widget")).unwrap();
+    let script = bundle.join("scripts/restore-portable.sh");
+    fs::write(&script, scripts::restore_portable_sh()).unwrap();
+    let bin = dir.path().join("bin");
+    fs::create_dir_all(&bin).unwrap();
+    let installed = dir.path().join("installed");
+    let kpackagetool = bin.join("kpackagetool6");
+    fs::write(
+        &kpackagetool,
+        format!(
+            "#!/bin/sh
+touch '{}'
+",
+            installed.display()
+        ),
+    )
+    .unwrap();
+    fs::set_permissions(&kpackagetool, fs::Permissions::from_mode(0o755)).unwrap();
+
+    for arg in ["DP-1", "=HDMI-A-1", "DP-1=HDMI\"};evil();//"] {
+        let out = Command::new("bash")
+            .arg(&script)
+            .args(["--map-screen", arg])
+            .env(
+                "PATH",
+                format!("{}:{}", bin.display(), std::env::var("PATH").unwrap()),
+            )
+            .env("HOME", dir.path())
+            .output()
+            .unwrap();
+        assert!(!out.status.success(), "{arg}");
+        let stdout = String::from_utf8_lossy(&out.stdout);
+        assert!(stdout.contains("--map-screen needs FROM=TO"), "{stdout}");
+    }
+    assert!(!installed.exists());
+}
+
+#[test]
+fn restore_script_adds_widgets_in_applet_order() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join(APPLETSRC);
+    fs::write(
+        &path,
+        "[Containments][1]
+formfactor=2
+location=4
+plugin=org.kde.panel
+
+[Containments][1][Applets][2]
+plugin=org.kde.plasma.kickoff
+
+[Containments][1][Applets][3]
+plugin=org.kde.plasma.digitalclock
+
+[Containments][1][General]
+AppletOrder=3;2
+",
+    )
+    .unwrap();
+    let layout = parser::parse_appletsrc(&path.display().to_string()).unwrap();
+    let js = scripts::restore_layout_js(&layout, &RestoreOpts::default());
+    let kickoff = js.find(r#"addWidget("org.kde.plasma.kickoff")"#).unwrap();
+    let clock = js
+        .find(r#"addWidget("org.kde.plasma.digitalclock")"#)
+        .unwrap();
+    assert!(clock < kickoff);
+}
+
+#[test]
+fn activities_keep_the_order_of_kactivitymanagerdrc() {
+    let dir = tempfile::tempdir().unwrap();
+    let rc = dir.path().join("kactivitymanagerdrc");
+    fs::write(&rc, "[activities]\nffff=Work\n0000=Home\n8888=Games\n").unwrap();
+    let mut layout = load();
+    layout.activities.clear();
+    activities::attach_activities(&mut layout, &rc.display().to_string(), &[]).unwrap();
+    let names: Vec<&str> = layout.activities.iter().map(|a| a.name.as_str()).collect();
+    assert_eq!(names, ["Work", "Home", "Games"]);
+}
+
+#[test]
+fn reexport_finds_renamed_wallpapers() {
+    let home = tempfile::tempdir().unwrap();
+    let pics = home.path().join("pics");
+    for (sub, content) in [("a", "A"), ("b", "B")] {
+        fs::create_dir_all(pics.join(sub)).unwrap();
+        fs::write(pics.join(sub).join("x.jpg"), content).unwrap();
+    }
+    let config = home.path().join(".config");
+    fs::create_dir_all(&config).unwrap();
+    fs::write(
+        config.join(APPLETSRC),
+        format!(
+            "[Containments][7]
+plugin=org.kde.plasma.folder
+wallpaperplugin=org.kde.image
+
+[Containments][7][Wallpaper][org.kde.image][General]
+Image=file://{pics}/a/x.jpg
+PreviewImage={pics}/b/x.jpg
+",
+            pics = pics.display()
+        ),
+    )
+    .unwrap();
+    let paths = Paths::with_root(home.path());
+    let layout = input::load_config(&paths, ConfigFiles::default()).unwrap();
+
+    let out = tempfile::tempdir().unwrap();
+    let first = Exporter::new(out.path().join("first"))
+        .paths(paths.clone())
+        .bundle_plasmoids(false)
+        .export(&layout)
+        .unwrap();
+    fs::remove_dir_all(&pics).unwrap();
+
+    // The second file was renamed on the collision; both come back.
+    let loaded = input::load(&first.dir).unwrap();
+    let second = Exporter::new(out.path().join("second"))
+        .paths(paths)
+        .bundle_plasmoids(false)
+        .source_bundle(loaded.bundle_dir.unwrap())
+        .export(&loaded.layout)
+        .unwrap();
+    let wallpapers = second.dir.join("wallpapers");
+    assert_eq!(fs::read_to_string(wallpapers.join("7-x.jpg")).unwrap(), "A");
+    assert_eq!(
+        fs::read_to_string(wallpapers.join("7-1-x.jpg")).unwrap(),
+        "B"
+    );
+}